@@ -3,7 +3,6 @@ use std::cmp::Ordering;
 use chrono::{DateTime, Timelike};
 use serde::{Deserialize, Serialize};
 
-#[allow(dead_code)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OrderType {
     Buy = 0,
@@ -12,7 +11,6 @@ pub enum OrderType {
     SellStop = 5,
 }
 
-#[allow(dead_code)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OrderSnapshot {
     pub ticket: i64,
@@ -29,12 +27,62 @@ pub struct OrderSnapshot {
 }
 
 impl OrderSnapshot {
-    fn total_profit(&self) -> f64 {
+    pub fn total_profit(&self) -> f64 {
         self.profit + self.swap + self.commission
     }
 }
 
+/// MT4 trade errors (`GetLastError()` after a failed `OrderSend`/`OrderModify`/`OrderClose`/
+/// `OrderDelete`). The simulator raises only some of them; the rest exist so the strategy
+/// handles a live bridge the way the EA does.
 #[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TradeError {
+    CommonError = 2,
+    ServerBusy = 4,
+    NoConnection = 6,
+    TradeTimeout = 128,
+    InvalidPrice = 129,
+    InvalidStops = 130,
+    InvalidTradeVolume = 131,
+    MarketClosed = 132,
+    TradeDisabled = 133,
+    NotEnoughMoney = 134,
+    PriceChanged = 135,
+    OffQuotes = 136,
+    BrokerBusy = 137,
+    Requote = 138,
+    TooManyRequests = 141,
+    TradeModifyDenied = 145,
+    TradeContextBusy = 146,
+    InvalidTicket = 4108,
+    TradeNotAllowed = 4109,
+}
+
+impl TradeError {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Errors that may clear on their own (stale quotes, busy server or trade context), so
+    /// repeating the request after a refresh makes sense.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            Self::ServerBusy
+                | Self::NoConnection
+                | Self::TradeTimeout
+                | Self::InvalidPrice
+                | Self::PriceChanged
+                | Self::OffQuotes
+                | Self::BrokerBusy
+                | Self::Requote
+                | Self::TooManyRequests
+                | Self::TradeContextBusy
+        )
+    }
+}
+
 pub trait BrokerApi {
     fn symbol(&self) -> &str;
     fn digits(&self) -> i32;
@@ -55,13 +103,13 @@ pub trait BrokerApi {
         lots: f64,
         price: f64,
         comment: &str,
-    ) -> Option<i64>;
-    fn modify_order(&mut self, ticket: i64, new_price: f64) -> bool;
-    fn close_order(&mut self, ticket: i64) -> bool;
-    fn delete_order(&mut self, ticket: i64) -> bool;
+    ) -> Result<i64, TradeError>;
+    fn modify_order(&mut self, ticket: i64, new_price: f64) -> Result<(), TradeError>;
+    fn close_order(&mut self, ticket: i64) -> Result<(), TradeError>;
+    fn delete_order(&mut self, ticket: i64) -> Result<(), TradeError>;
 }
 
-#[allow(dead_code, non_snake_case)]
+#[allow(non_snake_case)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Amazing31Params {
     pub On_top_of_this_price_not_Buy_first_order: f64,
@@ -177,9 +225,12 @@ struct RuntimeState {
     last_bar_time: i64,
     peak_buy_diff: f64,
     peak_sell_diff: f64,
+    close_alerts: usize,
+    last_close_error: Option<TradeError>,
+    order_errors: usize,
+    last_order_error: Option<TradeError>,
 }
 
-#[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct Amazing31Mt4 {
     pub params: Amazing31Params,
@@ -497,11 +548,15 @@ impl Amazing31Mt4 {
         );
     }
 
+    /// Closes/deletes every order on `side` (1 buy, -1 sell, 0 both). Retryable errors get
+    /// another pass, up to 10; a fatal one, or running out of passes, takes the EA's
+    /// "closed more than 10 times" alert path and gives up.
     pub fn lizong_7(&mut self, broker: &mut dyn BrokerApi, side: i32) -> bool {
         for _ in 0..10 {
             let mut remain = 0;
+            let mut fatal = None;
             for o in self.my_orders(broker) {
-                let ok = if matches!(o.order_type, OrderType::Buy | OrderType::BuyStop)
+                let res = if matches!(o.order_type, OrderType::Buy | OrderType::BuyStop)
                     && (side == 1 || side == 0)
                 {
                     if o.order_type == OrderType::Buy {
@@ -518,21 +573,69 @@ impl Amazing31Mt4 {
                         broker.delete_order(o.ticket)
                     }
                 } else {
-                    true
+                    Ok(())
                 };
 
-                if !ok {
+                if let Err(e) = res {
                     remain += 1;
+                    self.state.last_close_error = Some(e);
+                    if !e.is_retryable() {
+                        fatal = Some(e);
+                    }
                 }
             }
 
             if remain == 0 {
                 return true;
             }
+            if fatal.is_some() {
+                break;
+            }
         }
+        self.state.close_alerts += 1;
         false
     }
 
+    /// Times `lizong_7` gave up with orders still open (the EA's alert).
+    pub fn close_alerts(&self) -> usize {
+        self.state.close_alerts
+    }
+
+    /// Error that last made `lizong_7` leave an order open.
+    pub fn last_close_error(&self) -> Option<TradeError> {
+        self.state.last_close_error
+    }
+
+    /// Pending-order sends and modifications that failed for good (the EA's "开单失败" and
+    /// "Order Modify" error prints).
+    pub fn order_errors(&self) -> usize {
+        self.state.order_errors
+    }
+
+    /// Error of the last pending-order send or modification that failed for good.
+    pub fn last_order_error(&self) -> Option<TradeError> {
+        self.state.last_order_error
+    }
+
+    /// Repeats a pending-order send or modification while it fails with a retryable error,
+    /// up to 10 tries as in `lizong_7`. A request that still fails is counted in
+    /// `order_errors` and the EA carries on with the tick, as it does after printing the
+    /// error.
+    fn order_request<T>(&mut self, mut request: impl FnMut() -> Result<T, TradeError>) {
+        for _ in 0..10 {
+            match request() {
+                Ok(_) => return,
+                Err(e) => {
+                    self.state.last_order_error = Some(e);
+                    if !e.is_retryable() {
+                        break;
+                    }
+                }
+            }
+        }
+        self.state.order_errors += 1;
+    }
+
     fn lizong_9(
         &mut self,
         broker: &mut dyn BrokerApi,
@@ -560,13 +663,13 @@ impl Amazing31Mt4 {
 
             let target = pool[0].clone();
             if mode == 1 && target.profit >= 0.0 {
-                if broker.close_order(target.ticket) {
+                if broker.close_order(target.ticket).is_ok() {
                     count -= 1;
                 }
             } else if mode == 1 && target.profit < 0.0 {
                 count -= 1;
             } else if mode == 2 && target.profit < 0.0 {
-                if broker.close_order(target.ticket) {
+                if broker.close_order(target.ticket).is_ok() {
                     count -= 1;
                 }
             } else if mode == 2 && target.profit >= 0.0 {
@@ -761,7 +864,7 @@ impl Amazing31Mt4 {
                 && (buy_lots - sell_lots).abs() < 1e-12);
 
         let comment = if ss_comment { "SS" } else { "NN" };
-        let _ = broker.send_pending(OrderType::BuyStop, lots, px, comment);
+        self.order_request(|| broker.send_pending(OrderType::BuyStop, lots, px, comment));
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -857,7 +960,7 @@ impl Amazing31Mt4 {
                 && (buy_lots - sell_lots).abs() < 1e-12);
 
         let comment = if ss_comment { "SS" } else { "NN" };
-        let _ = broker.send_pending(OrderType::SellStop, lots, px, comment);
+        self.order_request(|| broker.send_pending(OrderType::SellStop, lots, px, comment));
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -913,7 +1016,7 @@ impl Amazing31Mt4 {
             };
 
             if cond {
-                let _ = broker.modify_order(pending.ticket, px);
+                self.order_request(|| broker.modify_order(pending.ticket, px));
             }
         }
     }
@@ -971,7 +1074,7 @@ impl Amazing31Mt4 {
             };
 
             if cond {
-                let _ = broker.modify_order(pending.ticket, px);
+                self.order_request(|| broker.modify_order(pending.ticket, px));
             }
         }
     }
@@ -0,0 +1,332 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Datelike, Timelike, Weekday};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::bar_cache::Bars;
+use crate::import::write_bars;
+use crate::sim_broker::{BAR_SECONDS, Bar};
+use crate::symbol_spec::SymbolSpec;
+use crate::{read_bars_file, ts_to_utc};
+
+/// What to do with bars the audit flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditAction {
+    /// Only report.
+    Report,
+    /// Fail when any bar is flagged.
+    Reject,
+    /// Keep the first of duplicate bars, rebuild inconsistent OHLC and clip spikes.
+    Repair,
+    /// Remove flagged bars.
+    Drop,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditOptions {
+    pub action: AuditAction,
+    /// A bar whose range, or jump from the previous bar's close, exceeds this many times the
+    /// median bar range is a spike. Jumps across a gap in the data are not counted.
+    pub outlier_factor: f64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GapBuckets {
+    pub m15_to_1h: usize,
+    pub h1_to_4h: usize,
+    pub h4_to_24h: usize,
+    pub over_24h: usize,
+    pub weekend: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AuditReport {
+    pub action: Option<AuditAction>,
+    pub bars_in: usize,
+    pub bars_out: usize,
+    pub unparsable_rows: usize,
+    pub duplicates: usize,
+    pub bad_prices: usize,
+    pub ohlc_inconsistent: usize,
+    pub outliers: usize,
+    pub repaired: usize,
+    pub dropped: usize,
+    pub gaps: GapBuckets,
+    /// Bars stamped between Friday 22:00 and Sunday 21:00 UTC, when FX markets are shut.
+    pub weekend_bars: usize,
+    pub weekends_covered: usize,
+    pub median_range: f64,
+    /// The first flagged bars, for a quick look.
+    pub samples: Vec<String>,
+}
+
+impl AuditReport {
+    pub fn flagged(&self) -> usize {
+        self.duplicates + self.bad_prices + self.ohlc_inconsistent + self.outliers
+    }
+
+    pub fn print_summary(&self) {
+        println!(
+            "audit: bars={} unparsable={} duplicates={} bad_prices={} ohlc_inconsistent={} outliers={} repaired={} dropped={}",
+            self.bars_in,
+            self.unparsable_rows,
+            self.duplicates,
+            self.bad_prices,
+            self.ohlc_inconsistent,
+            self.outliers,
+            self.repaired,
+            self.dropped
+        );
+        let g = &self.gaps;
+        println!(
+            "audit: gaps 15m-1h={} 1h-4h={} 4h-24h={} >24h={} weekend={} weekend_bars={} weekends={}",
+            g.m15_to_1h,
+            g.h1_to_4h,
+            g.h4_to_24h,
+            g.over_24h,
+            g.weekend,
+            self.weekend_bars,
+            self.weekends_covered
+        );
+        for s in &self.samples {
+            println!("  {s}");
+        }
+    }
+}
+
+/// Checks `bars` (sorted by time) and applies `opts.action` to the flagged ones.
+pub fn audit_bars(
+    bars: Bars,
+    unparsable_rows: usize,
+    opts: &AuditOptions,
+) -> Result<(Bars, AuditReport)> {
+    let mut report = AuditReport {
+        action: Some(opts.action),
+        bars_in: bars.len(),
+        unparsable_rows,
+        median_range: median_range(&bars),
+        ..AuditReport::default()
+    };
+    let spike = report.median_range * opts.outlier_factor;
+
+    // Only dropping and repairing build a new series; otherwise the bars are kept as given.
+    let edits = matches!(opts.action, AuditAction::Drop | AuditAction::Repair);
+    let mut out: Vec<Bar> = Vec::with_capacity(if edits { bars.len() } else { 0 });
+    for (i, b) in bars.iter().enumerate() {
+        let prev = if edits {
+            out.last()
+        } else {
+            i.checked_sub(1).map(|j| &bars[j])
+        };
+        let issue = if prev.is_some_and(|p| p.ts == b.ts) {
+            report.duplicates += 1;
+            Some("duplicate")
+        } else if [b.open, b.high, b.low, b.close]
+            .iter()
+            .any(|v| !v.is_finite() || *v <= 0.0)
+        {
+            report.bad_prices += 1;
+            Some("bad price")
+        } else if b.high < b.low || b.high < b.open.max(b.close) || b.low > b.open.min(b.close) {
+            report.ohlc_inconsistent += 1;
+            Some("ohlc inconsistent")
+        } else if spike > 0.0
+            && (b.high - b.low > spike
+                || prev.is_some_and(|p| {
+                    b.ts - p.ts <= 3 * BAR_SECONDS && (b.open - p.close).abs() > spike
+                }))
+        {
+            report.outliers += 1;
+            Some("outlier")
+        } else {
+            None
+        };
+
+        let Some(issue) = issue else {
+            if edits {
+                out.push(b.clone());
+            }
+            continue;
+        };
+        if report.samples.len() < 20 {
+            report.samples.push(format!(
+                "{} {} o={} h={} l={} c={}",
+                ts_to_utc(b.ts),
+                issue,
+                b.open,
+                b.high,
+                b.low,
+                b.close
+            ));
+        }
+
+        match opts.action {
+            AuditAction::Report | AuditAction::Reject => {}
+            AuditAction::Drop => report.dropped += 1,
+            AuditAction::Repair => {
+                let anchor = out.last().map(|p| p.close);
+                if issue == "duplicate" {
+                    report.dropped += 1;
+                    continue;
+                }
+                let mut b = b.clone();
+                if !repair_bar(&mut b, anchor, spike) {
+                    report.dropped += 1;
+                    continue;
+                }
+                report.repaired += 1;
+                out.push(b);
+            }
+        }
+    }
+
+    let out = if edits { out.into() } else { bars };
+    count_gaps(&out, &mut report);
+    report.bars_out = out.len();
+    if opts.action == AuditAction::Reject && report.flagged() > 0 {
+        report.print_summary();
+        bail!("data audit flagged {} bars", report.flagged());
+    }
+    Ok((out, report))
+}
+
+/// Replaces bad prices with the previous close, clips spikes to `spike` around it and
+/// rebuilds high/low around open/close. Returns false when nothing sane is left to keep.
+fn repair_bar(b: &mut Bar, anchor: Option<f64>, spike: f64) -> bool {
+    let fallback = anchor.unwrap_or(f64::NAN);
+    for v in [&mut b.open, &mut b.high, &mut b.low, &mut b.close] {
+        if !v.is_finite() || *v <= 0.0 {
+            *v = fallback;
+        }
+        if let Some(a) = anchor
+            && spike > 0.0
+        {
+            *v = v.clamp(a - spike, a + spike);
+        }
+    }
+    if [b.open, b.high, b.low, b.close]
+        .iter()
+        .any(|v| !v.is_finite())
+    {
+        return false;
+    }
+    b.high = b.high.max(b.open).max(b.close);
+    b.low = b.low.min(b.open).min(b.close);
+    true
+}
+
+fn median_range(bars: &[Bar]) -> f64 {
+    let mut ranges: Vec<f64> = bars
+        .iter()
+        .map(|b| b.high - b.low)
+        .filter(|r| r.is_finite() && *r > 0.0)
+        .collect();
+    if ranges.is_empty() {
+        return 0.0;
+    }
+    let mid = ranges.len() / 2;
+    *ranges.select_nth_unstable_by(mid, |a, b| a.total_cmp(b)).1
+}
+
+fn count_gaps(bars: &[Bar], report: &mut AuditReport) {
+    let mut last_weekend = None;
+    for b in bars {
+        if in_weekend_close(b.ts) {
+            report.weekend_bars += 1;
+        }
+    }
+    for w in bars.windows(2) {
+        let gap = w[1].ts - w[0].ts;
+        if gap <= 3 * BAR_SECONDS {
+            continue;
+        }
+        let saturday = (w[0].ts.div_euclid(86_400)..=w[1].ts.div_euclid(86_400)).find(|d| {
+            DateTime::from_timestamp(d * 86_400, 0).is_some_and(|dt| dt.weekday() == Weekday::Sat)
+        });
+        let g = &mut report.gaps;
+        if let Some(sat) = saturday {
+            g.weekend += 1;
+            if last_weekend != Some(sat) {
+                last_weekend = Some(sat);
+                report.weekends_covered += 1;
+            }
+        } else if gap <= 3600 {
+            g.m15_to_1h += 1;
+        } else if gap <= 4 * 3600 {
+            g.h1_to_4h += 1;
+        } else if gap <= 24 * 3600 {
+            g.h4_to_24h += 1;
+        } else {
+            g.over_24h += 1;
+        }
+    }
+}
+
+fn in_weekend_close(ts: i64) -> bool {
+    let Some(dt) = DateTime::from_timestamp(ts, 0) else {
+        return false;
+    };
+    match dt.weekday() {
+        Weekday::Fri => dt.hour() >= 22,
+        Weekday::Sat => true,
+        Weekday::Sun => dt.hour() < 21,
+        _ => false,
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct AuditArgs {
+    #[arg(long, default_value = "AUDNZD")]
+    pub symbol: String,
+    /// CSV or MT4 `.hst`/`.fxt` file to audit; defaults to the merged file the optimizer would pick.
+    #[arg(long)]
+    pub data_file: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = AuditAction::Report)]
+    pub action: AuditAction,
+    #[arg(long, default_value_t = 30.0)]
+    pub outlier_factor: f64,
+    /// Where to write the repaired/cleaned bars for --action repair or drop.
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+    /// Where to write the report as JSON.
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+    #[arg(long, default_value = "symbols.json")]
+    pub symbols_file: PathBuf,
+}
+
+pub fn run_audit(args: &AuditArgs) -> Result<()> {
+    let path = match &args.data_file {
+        Some(p) => p.clone(),
+        None => {
+            let today = chrono::Utc::now().date_naive();
+            crate::select_merged_file(&args.symbol, today, today)?
+        }
+    };
+    let (bars, unparsable, _) = read_bars_file(&path)?;
+    println!("data={}", path.display());
+
+    let opts = AuditOptions {
+        action: args.action,
+        outlier_factor: args.outlier_factor,
+    };
+    let (bars, report) = audit_bars(bars.into(), unparsable, &opts)?;
+    report.print_summary();
+
+    if let Some(p) = &args.report {
+        fs::write(p, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("failed to write {}", p.display()))?;
+        println!("Saved report: {}", p.display());
+    }
+    if let Some(p) = &args.out {
+        let spec = SymbolSpec::load(&args.symbols_file, &args.symbol)?
+            .unwrap_or_else(|| SymbolSpec::fallback(&args.symbol));
+        write_bars(p, &bars, spec.digits)?;
+        println!("Saved bars: {}", p.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,300 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use memmap2::Mmap;
+
+use crate::read_bars_csv;
+use crate::sim_broker::Bar;
+
+const MAGIC: &[u8; 8] = b"A31BARS1";
+/// Magic, source CRC32, unparsable row count, bar count. A multiple of 8, so the records
+/// that follow stay aligned in the page-aligned map.
+const HEADER: usize = 8 + 4 + 4 + 8;
+/// One `Bar` as laid out in memory: `ts`, open, high, low, close and spread (NaN when the
+/// source had none), little-endian.
+const RECORD: usize = 48;
+
+const _: () =
+    assert!(size_of::<Bar>() == RECORD && align_of::<Bar>() <= 8 && HEADER.is_multiple_of(8));
+
+/// Bars of one CSV file in a compact binary file next to it (`<file>.barcache`). The cache
+/// is rebuilt whenever the CRC32 of the CSV no longer matches the one it was built from.
+pub struct BarCache {
+    map: Mmap,
+    len: usize,
+    unparsable: usize,
+}
+
+impl BarCache {
+    /// Writes the cache of `bars` parsed from a source with CRC32 `crc` and maps it.
+    fn build(path: &Path, crc: u32, bars: &[Bar], unparsable: usize) -> Result<Self> {
+        write_cache(path, crc, bars, unparsable)?;
+        println!("bar cache written: {}", path.display());
+        match Self::map(path, crc)? {
+            Some(cache) => Ok(cache),
+            None => bail!("bar cache {} is unreadable after writing", path.display()),
+        }
+    }
+
+    fn map(path: &Path, crc: u32) -> Result<Option<Self>> {
+        let Ok(file) = File::open(path) else {
+            return Ok(None);
+        };
+        // SAFETY: the cache is only ever replaced by renaming a fresh file over it, so the
+        // mapped file is not modified while mapped.
+        let map = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("failed to map {}", path.display()))?;
+        if map.len() < HEADER || &map[..8] != MAGIC || u32_at(&map, 8) != crc {
+            return Ok(None);
+        }
+        let len = u64_at(&map, 16) as usize;
+        if map.len() != HEADER + len * RECORD || map[HEADER..].as_ptr().align_offset(8) != 0 {
+            return Ok(None);
+        }
+        let unparsable = u32_at(&map, 12) as usize;
+        Ok(Some(Self {
+            map,
+            len,
+            unparsable,
+        }))
+    }
+
+    /// All cached bars, borrowed from the map.
+    fn bars(&self) -> &[Bar] {
+        // SAFETY: `map` checked that the records fill the map after the header and start
+        // 8-aligned. Each is a little-endian `Bar`, only little-endian targets map the cache,
+        // and every bit pattern is a valid `i64` or `f64`.
+        unsafe { std::slice::from_raw_parts(self.map[HEADER..].as_ptr().cast::<Bar>(), self.len) }
+    }
+
+    /// Indices of the bars in `[from_ts, to_ts)`.
+    fn range(&self, from_ts: i64, to_ts: i64) -> Range<usize> {
+        let bars = self.bars();
+        let lo = bars.partition_point(|b| b.ts < from_ts);
+        let hi = bars.partition_point(|b| b.ts < to_ts).max(lo);
+        lo..hi
+    }
+}
+
+/// Bars loaded for a run: a window borrowed from a mapped cache, or parsed bars. A window is
+/// only copied out of the map when something edits it.
+pub enum Bars {
+    Mapped {
+        cache: BarCache,
+        range: Range<usize>,
+    },
+    Owned(Vec<Bar>),
+}
+
+impl Bars {
+    /// The bars of `source` in `[from_ts, to_ts)` through its cache, with the number of
+    /// source rows that did not parse. The cache is built first if it is missing or stale;
+    /// when it cannot be, a warning is printed and the parsed bars are used.
+    pub fn load_cached(source: &Path, from_ts: i64, to_ts: i64) -> Result<(Self, usize)> {
+        let crc = source_crc(source)?;
+        let path = cache_path(source);
+        // Records are mapped as native bars, which only matches the file on little-endian.
+        let usable = cfg!(target_endian = "little");
+        if usable {
+            match BarCache::map(&path, crc) {
+                Ok(Some(cache)) => return Ok(Self::window(cache, from_ts, to_ts)),
+                Ok(None) => {}
+                Err(e) => println!("warning: {e:#}; rebuilding the bar cache"),
+            }
+        }
+
+        let (bars, unparsable) = read_bars_csv(source)?;
+        if usable {
+            match BarCache::build(&path, crc, &bars, unparsable) {
+                Ok(cache) => return Ok(Self::window(cache, from_ts, to_ts)),
+                Err(e) => println!("warning: {e:#}; using the csv without a bar cache"),
+            }
+        }
+        let bars = bars
+            .into_iter()
+            .filter(|b| b.ts >= from_ts && b.ts < to_ts)
+            .collect();
+        Ok((Self::Owned(bars), unparsable))
+    }
+
+    fn window(cache: BarCache, from_ts: i64, to_ts: i64) -> (Self, usize) {
+        let range = cache.range(from_ts, to_ts);
+        let unparsable = cache.unparsable;
+        (Self::Mapped { cache, range }, unparsable)
+    }
+
+    /// The bars for editing, copied out of the map the first time.
+    pub fn to_mut(&mut self) -> &mut Vec<Bar> {
+        if let Self::Mapped { .. } = self {
+            *self = Self::Owned(self.to_vec());
+        }
+        match self {
+            Self::Owned(bars) => bars,
+            Self::Mapped { .. } => unreachable!("copied above"),
+        }
+    }
+}
+
+impl Deref for Bars {
+    type Target = [Bar];
+
+    fn deref(&self) -> &[Bar] {
+        match self {
+            Self::Mapped { cache, range } => &cache.bars()[range.clone()],
+            Self::Owned(bars) => bars,
+        }
+    }
+}
+
+impl From<Vec<Bar>> for Bars {
+    fn from(bars: Vec<Bar>) -> Self {
+        Self::Owned(bars)
+    }
+}
+
+fn cache_path(source: &Path) -> PathBuf {
+    let mut name = source.as_os_str().to_owned();
+    name.push(".barcache");
+    PathBuf::from(name)
+}
+
+fn source_crc(source: &Path) -> Result<u32> {
+    let file =
+        File::open(source).with_context(|| format!("failed to open {}", source.display()))?;
+    let mut reader = BufReader::with_capacity(1 << 20, file);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0_u8; 1 << 20];
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .with_context(|| format!("failed to read {}", source.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Writes to a temporary file and renames it into place, so a mapped cache is never
+/// modified under a reader.
+fn write_cache(path: &Path, crc: u32, bars: &[Bar], unparsable: usize) -> Result<()> {
+    let tmp = path.with_extension("barcache.tmp");
+    let file = File::create(&tmp).with_context(|| format!("failed to create {}", tmp.display()))?;
+    let mut w = BufWriter::new(file);
+    w.write_all(MAGIC)?;
+    w.write_all(&crc.to_le_bytes())?;
+    w.write_all(&(unparsable as u32).to_le_bytes())?;
+    w.write_all(&(bars.len() as u64).to_le_bytes())?;
+    for b in bars {
+        w.write_all(&b.ts.to_le_bytes())?;
+        for v in [b.open, b.high, b.low, b.close, b.spread] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+    }
+    w.flush()
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    drop(w);
+    fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().expect("4 bytes"))
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().expect("8 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "timestamp,open,high,low,close,spread\n\
+                       1000000,1.1,1.3,1.0,1.2,12\n\
+                       1300000,1.2,1.4,1.1,1.3,\n\
+                       bad,1,1,1,1,\n\
+                       1600000,1.3,1.5,1.2,1.4,15\n";
+
+    /// `(ts, close, spread)` of each bar.
+    fn summary(bars: &[Bar]) -> Vec<(i64, f64, Option<f64>)> {
+        bars.iter().map(|b| (b.ts, b.close, b.spread())).collect()
+    }
+
+    fn load(source: &Path) -> (Bars, usize) {
+        Bars::load_cached(source, i64::MIN, i64::MAX).unwrap()
+    }
+
+    #[test]
+    fn cache_round_trips_the_parsed_bars() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("bars.csv");
+        fs::write(&source, CSV).unwrap();
+
+        let (built, unparsable) = load(&source);
+        assert!(matches!(built, Bars::Mapped { .. }));
+        assert_eq!(unparsable, 1);
+        let expected = vec![
+            (1000, 1.2, Some(12.0)),
+            (1300, 1.3, None),
+            (1600, 1.4, Some(15.0)),
+        ];
+        assert_eq!(summary(&built), expected);
+        assert_eq!(
+            fs::metadata(cache_path(&source)).unwrap().len() as usize,
+            HEADER + 3 * RECORD
+        );
+
+        // Read back from the existing cache, as a window.
+        let (mapped, unparsable) = Bars::load_cached(&source, 1300, 1600).unwrap();
+        assert_eq!(unparsable, 1);
+        assert_eq!(summary(&mapped), expected[1..2]);
+    }
+
+    #[test]
+    fn cache_is_rebuilt_when_the_source_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("bars.csv");
+        fs::write(&source, CSV).unwrap();
+        load(&source);
+
+        fs::write(
+            &source,
+            "timestamp,open,high,low,close\n1900000,1.4,1.6,1.3,1.5\n",
+        )
+        .unwrap();
+        let (bars, unparsable) = load(&source);
+        assert_eq!(unparsable, 0);
+        assert_eq!(summary(&bars), [(1900, 1.5, None)]);
+        let crc = source_crc(&source).unwrap();
+        assert!(BarCache::map(&cache_path(&source), crc).unwrap().is_some());
+    }
+
+    #[test]
+    fn truncated_or_corrupt_cache_is_rejected_and_rebuilt() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("bars.csv");
+        fs::write(&source, CSV).unwrap();
+        let (bars, _) = load(&source);
+        let expected = summary(&bars);
+        drop(bars);
+        let path = cache_path(&source);
+        let crc = source_crc(&source).unwrap();
+        let good = fs::read(&path).unwrap();
+
+        let mut bad_magic = good.clone();
+        bad_magic[0] ^= 0xff;
+        for bytes in [&good[..good.len() - 8], &good[..5], &bad_magic[..]] {
+            fs::write(&path, bytes).unwrap();
+            assert!(BarCache::map(&path, crc).unwrap().is_none());
+            let (bars, unparsable) = load(&source);
+            assert_eq!(unparsable, 1);
+            assert_eq!(summary(&bars), expected);
+            assert_eq!(fs::read(&path).unwrap(), good);
+        }
+    }
+}
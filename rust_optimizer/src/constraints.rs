@@ -0,0 +1,421 @@
+use anyhow::{Result, anyhow, bail};
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use crate::search_space::ParamKind;
+use crate::{quantize_f64, quantize_i64, round_to};
+
+const EPS: f64 = 1e-9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cmp {
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    Eq,
+    Ne,
+}
+
+/// Which values of a repaired input satisfy a relation, relative to its bound.
+#[derive(Clone, Copy, Debug)]
+enum Limit {
+    Exact,
+    Lower { strict: bool },
+    Upper { strict: bool },
+}
+
+/// A comparison between two linear expressions of strategy inputs, such as
+/// `max_lot >= lot + 0.01` or `2 * step - two_step <= 50`. Bool inputs count as 0 or 1.
+#[derive(Clone, Debug)]
+pub struct Relation {
+    text: String,
+    /// `lhs - rhs` as coefficients per input, in order of appearance, plus a constant.
+    terms: Vec<(String, f64)>,
+    constant: f64,
+    cmp: Cmp,
+    /// Inputs of the left side, in order of appearance.
+    lhs_names: Vec<String>,
+}
+
+impl Serialize for Relation {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(&self.text)
+    }
+}
+
+impl Relation {
+    pub fn parse(text: &str) -> Result<Self> {
+        let ops = [
+            (">=", Cmp::Ge),
+            ("<=", Cmp::Le),
+            ("==", Cmp::Eq),
+            ("!=", Cmp::Ne),
+            (">", Cmp::Gt),
+            ("<", Cmp::Lt),
+        ];
+        let Some((at, op, cmp)) = ops
+            .iter()
+            .filter_map(|&(op, cmp)| text.find(op).map(|at| (at, op, cmp)))
+            .min_by_key(|&(at, op, _)| (at, std::cmp::Reverse(op.len())))
+        else {
+            bail!("{text:?}: expected one of >=, <=, >, <, ==, !=");
+        };
+        let (lhs, rhs) = (&text[..at], &text[at + op.len()..]);
+        if ops.iter().any(|(o, _)| rhs.contains(o)) {
+            bail!("{text:?}: only one comparison is allowed");
+        }
+
+        let (lhs_terms, lhs_const) =
+            parse_linear(lhs).map_err(|e| e.context(format!("{text:?}")))?;
+        let (rhs_terms, rhs_const) =
+            parse_linear(rhs).map_err(|e| e.context(format!("{text:?}")))?;
+        let lhs_names = lhs_terms.iter().map(|(n, _)| n.clone()).collect();
+        let mut terms = lhs_terms;
+        for (name, c) in rhs_terms {
+            add_term(&mut terms, name, -c);
+        }
+        terms.retain(|(_, c)| *c != 0.0);
+        Ok(Self {
+            text: text.trim().to_string(),
+            terms,
+            constant: lhs_const - rhs_const,
+            cmp,
+            lhs_names,
+        })
+    }
+
+    /// True for `!=`, which repair cannot enforce.
+    pub fn is_not_equal(&self) -> bool {
+        self.cmp == Cmp::Ne
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.terms.iter().map(|(n, _)| n.as_str())
+    }
+
+    pub fn lhs_names(&self) -> &[String] {
+        &self.lhs_names
+    }
+
+    pub fn holds(&self, p: &Map<String, Value>) -> bool {
+        let v = self.value(p);
+        match self.cmp {
+            Cmp::Ge => v >= -EPS,
+            Cmp::Gt => v > EPS,
+            Cmp::Le => v <= EPS,
+            Cmp::Lt => v < -EPS,
+            Cmp::Eq => v.abs() <= EPS,
+            Cmp::Ne => v.abs() > EPS,
+        }
+    }
+
+    /// Moves `target` to the allowed value nearest its current one that satisfies the
+    /// relation, as far as its range allows. Does nothing when the relation already holds.
+    pub fn repair(&self, p: &mut Map<String, Value>, target: &str, kind: &ParamKind) {
+        let Some((bound, limit)) = self.solve(p, target) else {
+            return;
+        };
+        let (lower, strict) = match limit {
+            Limit::Exact => {
+                set_nearest(p, target, kind, bound);
+                return;
+            }
+            Limit::Lower { strict } => (true, strict),
+            Limit::Upper { strict } => (false, strict),
+        };
+
+        match *kind {
+            ParamKind::Int { low, high, step } => {
+                let k = (bound - low as f64) / step as f64;
+                let mut k = if lower {
+                    (k - EPS).ceil()
+                } else {
+                    (k + EPS).floor()
+                } as i64;
+                if strict && ((low + k * step) as f64 - bound).abs() <= EPS {
+                    k += if lower { 1 } else { -1 };
+                }
+                let v = (low + k * step).clamp(low, high);
+                p.insert(target.to_string(), Value::from(v));
+            }
+            ParamKind::Float {
+                low,
+                high,
+                step,
+                precision,
+            } => {
+                let k = (bound - low) / step;
+                let mut k = if lower {
+                    (k - EPS).ceil()
+                } else {
+                    (k + EPS).floor()
+                };
+                if strict && (low + k * step - bound).abs() <= EPS {
+                    k += if lower { 1.0 } else { -1.0 };
+                }
+                let v = round_to((low + k * step).clamp(low, high), precision as i32);
+                p.insert(target.to_string(), Value::from(v));
+            }
+            ParamKind::Bool { .. } | ParamKind::Categorical { .. } => {}
+        }
+    }
+
+    /// Moves `target`, an input with no range, onto the bound of the relation: integers to
+    /// the nearest integer that satisfies it, floats to the bound itself (a strict bound is
+    /// passed by 1e-8). Does nothing when the relation already holds or `target` is not a
+    /// number.
+    pub fn repair_unbounded(&self, p: &mut Map<String, Value>, target: &str) {
+        let Some((bound, limit)) = self.solve(p, target) else {
+            return;
+        };
+        let v = match p.get(target) {
+            Some(v) if v.is_i64() || v.is_u64() => Value::from(match limit {
+                Limit::Exact => bound.round(),
+                Limit::Lower { strict: false } => (bound - EPS).ceil(),
+                Limit::Lower { strict: true } => (bound + EPS).floor() + 1.0,
+                Limit::Upper { strict: false } => (bound + EPS).floor(),
+                Limit::Upper { strict: true } => (bound - EPS).ceil() - 1.0,
+            } as i64),
+            Some(v) if v.is_f64() => Value::from(match limit {
+                Limit::Exact | Limit::Lower { strict: false } | Limit::Upper { strict: false } => {
+                    round_to(bound, 8)
+                }
+                Limit::Lower { strict: true } => round_to(bound + 1e-8, 8),
+                Limit::Upper { strict: true } => round_to(bound - 1e-8, 8),
+            }),
+            _ => return,
+        };
+        p.insert(target.to_string(), v);
+    }
+
+    /// The bound the relation puts on `target` with every other input held, and which side
+    /// of it is allowed; `None` when the relation holds, `target` is not in it, or it is `!=`.
+    fn solve(&self, p: &Map<String, Value>, target: &str) -> Option<(f64, Limit)> {
+        if self.holds(p) {
+            return None;
+        }
+        let &(_, a) = self.terms.iter().find(|(n, _)| n == target)?;
+        // a * target + rest (cmp) 0
+        let rest = self.value(p) - a * input(p, target);
+        let bound = -rest / a;
+        // Dividing by a negative coefficient flips the comparison.
+        let limit = match (self.cmp, a > 0.0) {
+            (Cmp::Ge, true) | (Cmp::Le, false) => Limit::Lower { strict: false },
+            (Cmp::Gt, true) | (Cmp::Lt, false) => Limit::Lower { strict: true },
+            (Cmp::Le, true) | (Cmp::Ge, false) => Limit::Upper { strict: false },
+            (Cmp::Lt, true) | (Cmp::Gt, false) => Limit::Upper { strict: true },
+            (Cmp::Eq, _) => Limit::Exact,
+            (Cmp::Ne, _) => return None,
+        };
+        Some((bound, limit))
+    }
+
+    fn value(&self, p: &Map<String, Value>) -> f64 {
+        self.terms.iter().map(|(n, c)| c * input(p, n)).sum::<f64>() + self.constant
+    }
+}
+
+fn set_nearest(p: &mut Map<String, Value>, target: &str, kind: &ParamKind, v: f64) {
+    match *kind {
+        ParamKind::Int { low, high, step } => {
+            let v = quantize_i64(v.round() as i64, low, high, step);
+            p.insert(target.to_string(), Value::from(v));
+        }
+        ParamKind::Float {
+            low,
+            high,
+            step,
+            precision,
+        } => {
+            p.insert(
+                target.to_string(),
+                Value::from(quantize_f64(v, low, high, step, precision)),
+            );
+        }
+        ParamKind::Bool { .. } | ParamKind::Categorical { .. } => {}
+    }
+}
+
+fn input(p: &Map<String, Value>, name: &str) -> f64 {
+    match p.get(name) {
+        Some(Value::Bool(b)) => f64::from(u8::from(*b)),
+        Some(v) => v.as_f64().unwrap_or(0.0),
+        None => 0.0,
+    }
+}
+
+fn add_term(terms: &mut Vec<(String, f64)>, name: String, c: f64) {
+    match terms.iter_mut().find(|(n, _)| *n == name) {
+        Some((_, acc)) => *acc += c,
+        None => terms.push((name, c)),
+    }
+}
+
+/// Parses `[-] term {(+|-) term}` where a term is a product of numbers and at most one input
+/// name.
+fn parse_linear(src: &str) -> Result<(Vec<(String, f64)>, f64)> {
+    let mut terms = Vec::new();
+    let mut constant = 0.0;
+    let mut chars = src.chars().peekable();
+    // The term being read: its sign, numeric factors and input name.
+    let (mut sign, mut coef, mut name) = (1.0, 1.0, None::<String>);
+    let mut has_factor = false;
+    let mut after_star = false;
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&c) = chars.peek() else { break };
+        match c {
+            '+' | '-' => {
+                if after_star {
+                    bail!("misplaced {c:?}");
+                }
+                chars.next();
+                let s = if c == '-' { -1.0 } else { 1.0 };
+                if has_factor {
+                    match name.take() {
+                        Some(n) => add_term(&mut terms, n, sign * coef),
+                        None => constant += sign * coef,
+                    }
+                    (sign, coef, has_factor) = (s, 1.0, false);
+                } else {
+                    sign *= s;
+                }
+            }
+            '*' => {
+                if !has_factor || after_star {
+                    bail!("misplaced '*'");
+                }
+                chars.next();
+                after_star = true;
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '.' => {
+                if has_factor && !after_star {
+                    bail!("missing operator before {c:?}");
+                }
+                let mut s = String::new();
+                while let Some(d) =
+                    chars.next_if(|d| d.is_ascii_alphanumeric() || *d == '_' || *d == '.')
+                {
+                    s.push(d);
+                }
+                if c.is_ascii_digit() || c == '.' {
+                    coef *= s.parse::<f64>().map_err(|_| anyhow!("bad number {s:?}"))?;
+                } else if name.is_some() {
+                    bail!("{s} multiplies another input; only linear expressions are allowed");
+                } else {
+                    name = Some(s);
+                }
+                (has_factor, after_star) = (true, false);
+            }
+            c => bail!("unexpected {c:?}"),
+        }
+    }
+    if !has_factor || after_star {
+        bail!("incomplete expression {:?}", src.trim());
+    }
+    match name {
+        Some(n) => add_term(&mut terms, n, sign * coef),
+        None => constant += sign * coef,
+    }
+    Ok((terms, constant))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn params(v: Value) -> Map<String, Value> {
+        v.as_object().expect("object").clone()
+    }
+
+    #[test]
+    fn parses_linear_sides() {
+        let r = Relation::parse("a >= b + 2").unwrap();
+        assert_eq!(r.lhs_names(), ["a"]);
+        assert_eq!(r.names().collect::<Vec<_>>(), ["a", "b"]);
+        assert!(r.holds(&params(json!({"a": 5, "b": 3}))));
+        assert!(!r.holds(&params(json!({"a": 4, "b": 3}))));
+
+        let r = Relation::parse("2 * step - two_step <= -0.5 * 10").unwrap();
+        assert!(r.holds(&params(json!({"step": 10, "two_step": 25}))));
+        assert!(!r.holds(&params(json!({"step": 10, "two_step": 24}))));
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        for text in [
+            "a",
+            "a >= b >= c",
+            "a * b >= 1",
+            "a >= ",
+            "a >= 2 b",
+            "a >= b +",
+        ] {
+            assert!(Relation::parse(text).is_err(), "{text:?} parsed");
+        }
+    }
+
+    #[test]
+    fn repairs_to_nearest_allowed_value() {
+        let r = Relation::parse("a >= b + 2").unwrap();
+        let kind = ParamKind::Int {
+            low: 0,
+            high: 100,
+            step: 5,
+        };
+        let mut p = params(json!({"a": 3, "b": 4}));
+        r.repair(&mut p, "a", &kind);
+        assert_eq!(p["a"], json!(10));
+
+        // The right-hand side is moved down when it is the target.
+        let mut p = params(json!({"a": 3, "b": 4}));
+        r.repair(&mut p, "b", &kind);
+        assert_eq!(p["b"], json!(0));
+
+        // Clamped to the range when the bound is out of reach; the relation still fails.
+        let mut p = params(json!({"a": 0, "b": 99}));
+        r.repair(&mut p, "a", &kind);
+        assert_eq!(p["a"], json!(100));
+        assert!(!r.holds(&p));
+    }
+
+    #[test]
+    fn repairs_strict_float_bounds() {
+        let r = Relation::parse("max_lot > lot + 0.01").unwrap();
+        let kind = ParamKind::Float {
+            low: 0.0,
+            high: 1.0,
+            step: 0.01,
+            precision: 2,
+        };
+        let mut p = params(json!({"max_lot": 0.01, "lot": 0.02}));
+        r.repair(&mut p, "max_lot", &kind);
+        assert_eq!(p["max_lot"], json!(0.04));
+    }
+
+    #[test]
+    fn repairs_unbounded_inputs_onto_the_bound() {
+        let r = Relation::parse("two_step >= step").unwrap();
+        let mut p = params(json!({"two_step": 100, "step": 150}));
+        r.repair_unbounded(&mut p, "two_step");
+        assert_eq!(p["two_step"], json!(150));
+
+        let r = Relation::parse("max_lot >= lot + 0.01").unwrap();
+        let mut p = params(json!({"max_lot": 0.01, "lot": 0.02}));
+        r.repair_unbounded(&mut p, "max_lot");
+        assert_eq!(p["max_lot"], json!(0.03));
+
+        let r = Relation::parse("a < b").unwrap();
+        let mut p = params(json!({"a": 7, "b": 5}));
+        r.repair_unbounded(&mut p, "a");
+        assert_eq!(p["a"], json!(4));
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+
+/// Deposit currency used when `--account-currency` is not given and a rate is available.
+pub const DEFAULT_ACCOUNT: &str = "USD";
+
+/// How an amount in the symbol's quote currency becomes deposit (account) currency.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum QuoteConversion {
+    /// The quote currency is the account currency (e.g. EURUSD on a USD account).
+    Unit,
+    /// The base currency is the account currency (e.g. USDJPY on a USD account): divide by
+    /// the symbol's own price.
+    InversePrice,
+    /// A fixed number of account units per quote unit.
+    Fixed { rate: f64 },
+    /// Closes of a second symbol pairing the quote and account currencies. `invert` is set
+    /// when that symbol is quoted as ACCOUNT/QUOTE rather than QUOTE/ACCOUNT.
+    Series {
+        symbol: String,
+        invert: bool,
+        #[serde(skip)]
+        rates: Arc<Vec<(i64, f64)>>,
+    },
+}
+
+impl QuoteConversion {
+    /// Works out the conversion for `symbol` on an `account` deposit. Without an explicit
+    /// account currency the deposit is in `DEFAULT_ACCOUNT` when a rate for it is available;
+    /// otherwise the symbol's base currency is used, which keeps the historical "divide by
+    /// price" behaviour.
+    pub fn resolve(
+        symbol: &str,
+        account: Option<&str>,
+        fixed_rate: Option<f64>,
+        series: Option<(&str, Vec<(i64, f64)>)>,
+    ) -> Result<(String, Self)> {
+        let explicit = account.is_some();
+        let account = account.unwrap_or(DEFAULT_ACCOUNT).trim().to_uppercase();
+        let Some((base, quote)) = split_pair(symbol) else {
+            if let Some(rate) = fixed_rate {
+                return Ok((account, Self::Fixed { rate }));
+            }
+            if explicit {
+                bail!("cannot derive currencies from symbol {symbol}; pass --conversion-rate");
+            }
+            return Ok((String::new(), Self::InversePrice));
+        };
+
+        if quote == account {
+            return Ok((account, Self::Unit));
+        }
+        if base == account {
+            return Ok((account, Self::InversePrice));
+        }
+        if let Some(rate) = fixed_rate {
+            return Ok((account, Self::Fixed { rate }));
+        }
+        if let Some((conv_symbol, rates)) = series {
+            let invert = match split_pair(conv_symbol) {
+                Some((b, q)) if b == quote && q == account => false,
+                Some((b, q)) if b == account && q == quote => true,
+                _ => bail!("conversion symbol {conv_symbol} does not pair {quote} with {account}"),
+            };
+            if rates.is_empty() {
+                bail!("conversion series for {conv_symbol} is empty");
+            }
+            return Ok((
+                account,
+                Self::Series {
+                    symbol: conv_symbol.to_uppercase(),
+                    invert,
+                    rates: Arc::new(rates),
+                },
+            ));
+        }
+        if !explicit {
+            return Ok((base, Self::InversePrice));
+        }
+        bail!(
+            "no {quote}->{account} conversion for {symbol}; pass --conversion-rate or \
+             --conversion-symbol, or --account-currency {quote} or {base}"
+        )
+    }
+
+    /// Symbols whose prices would convert `symbol`'s quote currency into `account`, as
+    /// QUOTE/ACCOUNT then ACCOUNT/QUOTE; empty when no conversion series is needed.
+    pub fn series_symbols(symbol: &str, account: Option<&str>) -> Vec<String> {
+        let account = account.unwrap_or(DEFAULT_ACCOUNT).trim().to_uppercase();
+        match split_pair(symbol) {
+            Some((base, quote)) if quote != account && base != account => {
+                vec![format!("{quote}{account}"), format!("{account}{quote}")]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Converts `amount` quote currency to account currency at time `ts`, where `price` is
+    /// the symbol's price the amount was realised at.
+    pub fn to_account(&self, amount: f64, price: f64, ts: i64) -> f64 {
+        match self {
+            Self::Unit => amount,
+            Self::InversePrice => {
+                if price <= 0.0 {
+                    0.0
+                } else {
+                    amount / price
+                }
+            }
+            Self::Fixed { rate } => amount * rate,
+            Self::Series { invert, rates, .. } => {
+                let idx = rates.partition_point(|(t, _)| *t <= ts).saturating_sub(1);
+                let px = rates[idx].1;
+                if *invert {
+                    if px <= 0.0 { 0.0 } else { amount / px }
+                } else {
+                    amount * px
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for QuoteConversion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unit => f.write_str("none (quote currency)"),
+            Self::InversePrice => f.write_str("1/price (base currency)"),
+            Self::Fixed { rate } => write!(f, "fixed rate {rate}"),
+            Self::Series { symbol, invert, .. } => {
+                write!(f, "{}{symbol} closes", if *invert { "1/" } else { "" })
+            }
+        }
+    }
+}
+
+fn split_pair(symbol: &str) -> Option<(String, String)> {
+    let s = symbol.trim().to_uppercase();
+    if s.len() != 6 || !s.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some((s[0..3].to_string(), s[3..6].to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_account_falls_back_to_the_base_currency_without_a_rate() {
+        let (account, conv) = QuoteConversion::resolve("AUDNZD", None, None, None).unwrap();
+        assert_eq!(account, "AUD");
+        assert!(matches!(conv, QuoteConversion::InversePrice));
+
+        let (account, conv) = QuoteConversion::resolve("AUDNZD", None, Some(0.5), None).unwrap();
+        assert_eq!(account, "USD");
+        assert!(matches!(conv, QuoteConversion::Fixed { rate } if rate == 0.5));
+    }
+
+    #[test]
+    fn explicit_account_needs_a_rate() {
+        assert!(QuoteConversion::resolve("AUDNZD", Some("usd"), None, None).is_err());
+        let (account, conv) = QuoteConversion::resolve("EURUSD", Some("usd"), None, None).unwrap();
+        assert_eq!(account, "USD");
+        assert!(matches!(conv, QuoteConversion::Unit));
+    }
+
+    #[test]
+    fn series_symbols_pair_quote_and_account() {
+        assert_eq!(
+            QuoteConversion::series_symbols("AUDNZD", None),
+            ["NZDUSD", "USDNZD"]
+        );
+        assert!(QuoteConversion::series_symbols("EURUSD", None).is_empty());
+        assert!(QuoteConversion::series_symbols("AUDNZD", Some("AUD")).is_empty());
+
+        let rates = vec![(0, 2.0)];
+        let (_, conv) =
+            QuoteConversion::resolve("AUDNZD", None, None, Some(("USDNZD", rates))).unwrap();
+        assert_eq!(conv.to_account(10.0, 1.1, 5), 5.0);
+    }
+}
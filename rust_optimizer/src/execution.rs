@@ -0,0 +1,171 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use chrono::{DateTime, Timelike};
+use clap::ValueEnum;
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use crate::sim_broker::Bar;
+use crate::symbol_spec::SymbolSpec;
+use crate::{abs_gauss, clamp};
+
+/// Spread quoted on a bar, in pips.
+pub trait SpreadModel: Debug + Send + Sync {
+    fn spread_pips(&self, bar: &Bar, spec: &SymbolSpec, rng: &mut StdRng) -> f64;
+}
+
+/// Adverse slippage in pips on a fill of `lots` inside `bar`.
+pub trait SlippageModel: Debug + Send + Sync {
+    fn slippage_pips(&self, bar: &Bar, lots: f64, spec: &SymbolSpec, rng: &mut StdRng) -> f64;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpreadModelKind {
+    /// The bar's recorded spread, falling back to `volatility` where the data has none.
+    Recorded,
+    /// Base spread widened by the bar's range, the hour of day and random noise.
+    Volatility,
+    /// Base spread widened around the daily rollover and outside the main sessions.
+    Session,
+    /// Always `--spread-pips`.
+    Fixed,
+    Zero,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SlippageModelKind {
+    /// Grows with the bar's range and the order size, plus random noise.
+    Volatility,
+    /// Grows around the daily rollover and outside the main sessions, and with order size.
+    Session,
+    /// Always `--slippage-pips`.
+    Fixed,
+    Zero,
+}
+
+impl SpreadModelKind {
+    pub fn build(self, fixed_pips: f64) -> Arc<dyn SpreadModel> {
+        match self {
+            Self::Recorded => Arc::new(RecordedSpread),
+            Self::Volatility => Arc::new(VolatilitySpread),
+            Self::Session => Arc::new(SessionSpread),
+            Self::Fixed => Arc::new(FixedSpread { pips: fixed_pips }),
+            Self::Zero => Arc::new(FixedSpread { pips: 0.0 }),
+        }
+    }
+}
+
+impl SlippageModelKind {
+    pub fn build(self, fixed_pips: f64) -> Arc<dyn SlippageModel> {
+        match self {
+            Self::Volatility => Arc::new(VolatilitySlippage),
+            Self::Session => Arc::new(SessionSlippage),
+            Self::Fixed => Arc::new(FixedSlippage { pips: fixed_pips }),
+            Self::Zero => Arc::new(FixedSlippage { pips: 0.0 }),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FixedSpread {
+    pips: f64,
+}
+
+impl SpreadModel for FixedSpread {
+    fn spread_pips(&self, _bar: &Bar, _spec: &SymbolSpec, _rng: &mut StdRng) -> f64 {
+        self.pips.max(0.0)
+    }
+}
+
+#[derive(Debug)]
+struct SessionSpread;
+
+impl SpreadModel for SessionSpread {
+    fn spread_pips(&self, bar: &Bar, _spec: &SymbolSpec, _rng: &mut StdRng) -> f64 {
+        0.55 + session_widening(bar.ts)
+    }
+}
+
+#[derive(Debug)]
+struct VolatilitySpread;
+
+impl SpreadModel for VolatilitySpread {
+    fn spread_pips(&self, bar: &Bar, spec: &SymbolSpec, rng: &mut StdRng) -> f64 {
+        let base = 0.55;
+        let vol_part = (0.018 * range_pips(bar, spec)).min(1.6);
+        let session = session_widening(bar.ts);
+        let noise = rng.random_range(-0.08..=0.12);
+        clamp(base + vol_part + session + noise, 0.25, 3.0)
+    }
+}
+
+#[derive(Debug)]
+struct RecordedSpread;
+
+impl SpreadModel for RecordedSpread {
+    fn spread_pips(&self, bar: &Bar, spec: &SymbolSpec, rng: &mut StdRng) -> f64 {
+        match bar.spread() {
+            Some(points) => points * spec.point / spec.pip(),
+            None => VolatilitySpread.spread_pips(bar, spec, rng),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FixedSlippage {
+    pips: f64,
+}
+
+impl SlippageModel for FixedSlippage {
+    fn slippage_pips(&self, _bar: &Bar, _lots: f64, _spec: &SymbolSpec, _rng: &mut StdRng) -> f64 {
+        self.pips.max(0.0)
+    }
+}
+
+#[derive(Debug)]
+struct SessionSlippage;
+
+impl SlippageModel for SessionSlippage {
+    fn slippage_pips(&self, bar: &Bar, lots: f64, _spec: &SymbolSpec, rng: &mut StdRng) -> f64 {
+        let session = session_widening(bar.ts);
+        let noise = abs_gauss(rng, 0.10);
+        (0.08 + session + size_component(lots) + noise).min(2.5)
+    }
+}
+
+#[derive(Debug)]
+struct VolatilitySlippage;
+
+impl SlippageModel for VolatilitySlippage {
+    fn slippage_pips(&self, bar: &Bar, lots: f64, spec: &SymbolSpec, rng: &mut StdRng) -> f64 {
+        let vol_component = (0.012 * range_pips(bar, spec)).min(1.2);
+        let noise = abs_gauss(rng, 0.10);
+        (0.08 + vol_component + size_component(lots) + noise).min(2.5)
+    }
+}
+
+fn range_pips(bar: &Bar, spec: &SymbolSpec) -> f64 {
+    ((bar.high - bar.low) / spec.pip()).max(0.0)
+}
+
+fn size_component(lots: f64) -> f64 {
+    ((lots - 0.05).max(0.0) * 0.18).min(0.6)
+}
+
+/// Extra pips around the daily rollover (21:00-01:59) and between the main sessions.
+fn session_widening(ts: i64) -> f64 {
+    let hour = DateTime::from_timestamp(ts, 0)
+        .map(|dt| dt.hour() as i32)
+        .unwrap_or(0);
+    if hour >= 21 || hour <= 1 {
+        0.45
+    } else if (6..=15).contains(&hour) {
+        0.0
+    } else {
+        0.15
+    }
+}
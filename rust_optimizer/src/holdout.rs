@@ -0,0 +1,251 @@
+use std::cmp::Ordering;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::sim_broker::{Bar, SimConfig};
+use crate::{
+    CandidateEval, YearResult, agg_num, evaluate_batch, fingerprint_params, is_feasible, ts_to_utc,
+};
+
+/// Trailing yearly windows kept out of the search.
+pub struct Holdout<'a> {
+    pub bars: &'a [&'a [Bar]],
+    /// Number of yearly windows before the hold-out.
+    pub first_year: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HoldoutRun {
+    score: f64,
+    aggregate: Value,
+    yearly_results: Vec<YearResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HoldoutCandidate {
+    /// 1-based ranks by in-sample and by hold-out score.
+    in_sample_rank: usize,
+    holdout_rank: usize,
+    in_sample_score: f64,
+    in_sample_feasible: bool,
+    params: Map<String, Value>,
+    #[serde(flatten)]
+    holdout: HoldoutRun,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutOfSample {
+    /// 1-based yearly windows, inclusive.
+    holdout_years: [usize; 2],
+    start_utc: String,
+    end_utc: String,
+    /// `in_sample` or `holdout`: what `selected_result` was picked by.
+    selected_by: &'static str,
+    selected_result: HoldoutRun,
+    best_feasible: Option<HoldoutRun>,
+    best_any: HoldoutRun,
+    candidates: Vec<HoldoutCandidate>,
+}
+
+impl OutOfSample {
+    /// The candidate with the best hold-out score among those feasible in sample (or among
+    /// all when none is), as an index into the `top` list given to `evaluate_holdout`.
+    /// Marks the result as selected by hold-out rank.
+    pub fn select_by_holdout(&mut self) -> usize {
+        let any_feasible = self.candidates.iter().any(|c| c.in_sample_feasible);
+        let Some(best) = self
+            .candidates
+            .iter()
+            .filter(|c| c.in_sample_feasible || !any_feasible)
+            .min_by_key(|c| c.holdout_rank)
+        else {
+            return 0;
+        };
+        self.selected_by = "holdout";
+        self.selected_result = best.holdout.clone();
+        best.in_sample_rank - 1
+    }
+
+    /// Hold-out net profit of the selected result.
+    pub fn selected_net_profit(&self) -> f64 {
+        agg_num(&self.selected_result.aggregate, "sum_net_profit")
+    }
+}
+
+/// Pairs the `top` candidates with their hold-out runs, which come first in `runs` (any runs
+/// after them belong to `best_any`/`best_feasible` and are dropped), and ranks them by
+/// hold-out score. The sort is stable, so equal scores keep their in-sample order.
+fn rank_candidates(top: &[CandidateEval], mut runs: Vec<HoldoutRun>) -> Vec<HoldoutCandidate> {
+    runs.truncate(top.len());
+    let mut order: Vec<usize> = (0..runs.len()).collect();
+    order.sort_by(|&a, &b| {
+        runs[b]
+            .score
+            .partial_cmp(&runs[a].score)
+            .unwrap_or(Ordering::Equal)
+    });
+    let mut holdout_rank = vec![0; runs.len()];
+    for (rank, &i) in order.iter().enumerate() {
+        holdout_rank[i] = rank + 1;
+    }
+    top.iter()
+        .zip(runs)
+        .enumerate()
+        .map(|(i, (c, run))| HoldoutCandidate {
+            in_sample_rank: i + 1,
+            holdout_rank: holdout_rank[i],
+            in_sample_score: c.score,
+            in_sample_feasible: is_feasible(&c.agg),
+            params: c.params.clone(),
+            holdout: run,
+        })
+        .collect()
+}
+
+/// Runs `best_any`, `best_feasible` and the `top` candidates (sorted by in-sample score) on
+/// the hold-out windows. `selected_result` starts as the in-sample pick: best feasible, else
+/// best overall.
+pub fn evaluate_holdout(
+    best_any: &CandidateEval,
+    best_feasible: Option<&CandidateEval>,
+    top: &[CandidateEval],
+    holdout: &Holdout,
+    symbol: &str,
+    sim: &SimConfig,
+    drawdown_limit: f64,
+) -> OutOfSample {
+    // The best candidates are normally the head of `top`, so each distinct one runs once.
+    let mut params: Vec<Map<String, Value>> = Vec::new();
+    let mut fingerprints: Vec<String> = Vec::new();
+    for c in top.iter().chain([best_any]).chain(best_feasible) {
+        let fp = fingerprint_params(&c.params);
+        if !fingerprints.contains(&fp) {
+            fingerprints.push(fp);
+            params.push(c.params.clone());
+        }
+    }
+    let runs: Vec<HoldoutRun> = evaluate_batch(params, holdout.bars, symbol, sim, drawdown_limit)
+        .into_iter()
+        .map(|mut ce| {
+            for y in &mut ce.years {
+                y.year_idx += holdout.first_year;
+            }
+            HoldoutRun {
+                score: ce.score,
+                aggregate: ce.agg,
+                yearly_results: ce.years,
+            }
+        })
+        .collect();
+    let run_of = |runs: &[HoldoutRun], c: &CandidateEval| {
+        let fp = fingerprint_params(&c.params);
+        let i = fingerprints
+            .iter()
+            .position(|f| *f == fp)
+            .expect("evaluated");
+        runs[i].clone()
+    };
+    let best_any_run = run_of(&runs, best_any);
+    let best_feasible_run = best_feasible.map(|c| run_of(&runs, c));
+    let selected_result = best_feasible_run.clone().unwrap_or(best_any_run.clone());
+    let candidates = rank_candidates(top, runs);
+
+    let clock = sim.server_time;
+    let first = holdout
+        .bars
+        .first()
+        .and_then(|b| b.first())
+        .map_or(0, |b| b.ts);
+    let last = holdout
+        .bars
+        .last()
+        .and_then(|b| b.last())
+        .map_or(0, |b| b.ts);
+    OutOfSample {
+        holdout_years: [
+            holdout.first_year + 1,
+            holdout.first_year + holdout.bars.len(),
+        ],
+        start_utc: ts_to_utc(clock.to_utc(first)),
+        end_utc: ts_to_utc(clock.to_utc(last)),
+        selected_by: "in_sample",
+        selected_result,
+        best_feasible: best_feasible_run,
+        best_any: best_any_run,
+        candidates,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn candidate(id: i64, score: f64, feasible: bool) -> CandidateEval {
+        let mut params = Map::new();
+        params.insert("id".to_string(), json!(id));
+        CandidateEval {
+            score,
+            params,
+            agg: json!({ "feasible_drawdown_limit": if feasible { 1.0 } else { 0.0 } }),
+            years: Vec::new(),
+        }
+    }
+
+    fn run(score: f64) -> HoldoutRun {
+        HoldoutRun {
+            score,
+            aggregate: json!({ "sum_net_profit": score }),
+            yearly_results: Vec::new(),
+        }
+    }
+
+    fn out_of_sample(candidates: Vec<HoldoutCandidate>) -> OutOfSample {
+        OutOfSample {
+            holdout_years: [4, 4],
+            start_utc: String::new(),
+            end_utc: String::new(),
+            selected_by: "in_sample",
+            selected_result: run(5.0),
+            best_feasible: None,
+            best_any: run(5.0),
+            candidates,
+        }
+    }
+
+    #[test]
+    fn holdout_picks_the_best_out_of_sample_score_among_feasible_candidates() {
+        // In-sample order; the last run is best_any's own and must not be paired.
+        let top = [
+            candidate(0, 40.0, true),
+            candidate(1, 30.0, true),
+            candidate(2, 20.0, true),
+            candidate(3, 10.0, false),
+        ];
+        let runs = [5.0, 9.0, 9.0, 12.0, 100.0].map(run).to_vec();
+        let candidates = rank_candidates(&top, runs);
+
+        let ranks: Vec<_> = candidates
+            .iter()
+            .map(|c| (c.in_sample_rank, c.holdout_rank, c.holdout.score))
+            .collect();
+        // Tied at 9, the better in-sample candidate ranks first.
+        assert_eq!(ranks, [(1, 4, 5.0), (2, 2, 9.0), (3, 3, 9.0), (4, 1, 12.0)]);
+
+        let mut oos = out_of_sample(candidates);
+        assert_eq!(oos.select_by_holdout(), 1);
+        assert_eq!(oos.selected_by, "holdout");
+        assert_eq!(oos.selected_net_profit(), 9.0);
+    }
+
+    #[test]
+    fn holdout_falls_back_to_all_candidates_when_none_is_feasible() {
+        let top = [candidate(0, 40.0, false), candidate(1, 30.0, false)];
+        let candidates = rank_candidates(&top, [3.0, 8.0].map(run).to_vec());
+        let mut oos = out_of_sample(candidates);
+        assert_eq!(oos.select_by_holdout(), 1);
+        assert_eq!(oos.selected_net_profit(), 8.0);
+    }
+}
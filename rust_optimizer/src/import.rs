@@ -0,0 +1,502 @@
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::sim_broker::{BAR_SECONDS, Bar};
+use crate::symbol_spec::SymbolSpec;
+use crate::ts_to_utc;
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    #[arg(long, default_value = "AUDNZD")]
+    pub symbol: String,
+    /// Directory (searched recursively) or single file of Dukascopy `.bi5` ticks and/or CSV
+    /// exports. Existing `-merged.csv` files and ask-side CSVs (an `ask` name part) are
+    /// skipped.
+    #[arg(long)]
+    pub input: PathBuf,
+    /// Write a tick file instead of M5 bars.
+    #[arg(long)]
+    pub ticks: bool,
+    #[arg(long, default_value = "download")]
+    pub out_dir: PathBuf,
+    /// Integer-price divisor of the `.bi5` records; defaults to 1 / point of the symbol spec.
+    #[arg(long)]
+    pub price_scale: Option<f64>,
+    #[arg(long, default_value = "symbols.json")]
+    pub symbols_file: PathBuf,
+}
+
+#[derive(Clone, Debug)]
+struct RawTick {
+    ts_ms: i64,
+    bid: f64,
+    ask: f64,
+}
+
+/// What one input file held.
+enum Parsed {
+    Ticks(Vec<RawTick>),
+    Bars(Vec<Bar>),
+}
+
+pub fn run_import(args: &ImportArgs) -> Result<()> {
+    let spec = SymbolSpec::load(&args.symbols_file, &args.symbol)?
+        .unwrap_or_else(|| SymbolSpec::fallback(&args.symbol));
+    let scale = args.price_scale.unwrap_or(1.0 / spec.point);
+
+    let mut files = Vec::new();
+    collect_files(&args.input, &mut files)?;
+    // A directory may hold several instruments; keep the files whose path names the symbol.
+    if args.input.is_dir() {
+        let sym = args.symbol.to_lowercase();
+        files.retain(|p| {
+            p.iter()
+                .any(|c| c.to_string_lossy().to_lowercase().contains(&sym))
+        });
+    }
+    // Ask-side OHLC exports would otherwise be merged as bid bars; they only pair with a
+    // bid file through `--ask-file`.
+    let (asks, mut files): (Vec<_>, Vec<_>) = files.into_iter().partition(|p| is_ask_side(p));
+    if !asks.is_empty() {
+        println!(
+            "skipped {} ask-side file(s), e.g. {}",
+            asks.len(),
+            asks[0].display()
+        );
+    }
+    files.sort();
+    if files.is_empty() {
+        bail!(
+            "no {} .bi5 or .csv files under {}",
+            args.symbol,
+            args.input.display()
+        );
+    }
+
+    let mut ticks = Vec::new();
+    let mut bars = Vec::new();
+    for path in &files {
+        match parse_file(path, scale)? {
+            Parsed::Ticks(t) => ticks.extend(t),
+            Parsed::Bars(b) => bars.extend(b),
+        }
+    }
+    println!(
+        "files={} ticks={} bars={}",
+        files.len(),
+        ticks.len(),
+        bars.len()
+    );
+
+    ticks.sort_by_key(|t| t.ts_ms);
+    // Overlapping inputs repeat whole ticks; distinct ticks may share a millisecond.
+    ticks.dedup_by(|a, b| a.ts_ms == b.ts_ms && a.bid == b.bid && a.ask == b.ask);
+
+    let out = if args.ticks {
+        if ticks.is_empty() {
+            bail!("--ticks needs tick input, found only bars");
+        }
+        let first = ticks[0].ts_ms.div_euclid(1000);
+        let last = ticks[ticks.len() - 1].ts_ms.div_euclid(1000);
+        let path = merged_path(&args.out_dir, &args.symbol, "ticks", first, last)?;
+        write_ticks(&path, &ticks, spec.digits)?;
+        path
+    } else {
+        let mut all = bars_from_ticks(&ticks, spec.point);
+        all.extend(to_m5(bars));
+        all.sort_by_key(|b| b.ts);
+        all.dedup_by_key(|b| b.ts);
+        if all.is_empty() {
+            bail!("no bars could be built from {}", args.input.display());
+        }
+        let (first, last) = (all[0].ts, all[all.len() - 1].ts);
+        let path = merged_path(&args.out_dir, &args.symbol, "m5-bid", first, last)?;
+        write_bars(&path, &all, spec.digits)?;
+        path
+    };
+    println!("Saved merged file: {}", out.display());
+    Ok(())
+}
+
+fn collect_files(path: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_file() {
+        out.push(path.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(path).with_context(|| format!("failed to read {}", path.display()))? {
+        let p = entry?.path();
+        if p.is_dir() {
+            collect_files(&p, out)?;
+            continue;
+        }
+        let Some(name) = p.file_name().and_then(|x| x.to_str()) else {
+            continue;
+        };
+        if name.ends_with(".bi5") || (name.ends_with(".csv") && !name.ends_with("-merged.csv")) {
+            out.push(p);
+        }
+    }
+    Ok(())
+}
+
+/// A CSV whose file name has an `ask` part, such as `audnzd-m5-ask-2024.csv` or Dukascopy's
+/// `AUDNZD_Candlestick_5_M_ASK_01.01.2024-31.12.2024.csv`.
+fn is_ask_side(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "csv")
+        && path.file_stem().and_then(|n| n.to_str()).is_some_and(|n| {
+            n.split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|part| part.eq_ignore_ascii_case("ask"))
+        })
+}
+
+fn parse_file(path: &Path, scale: f64) -> Result<Parsed> {
+    if path.extension().is_some_and(|e| e == "bi5") {
+        let hour_ms = bi5_hour_start(path)
+            .with_context(|| format!("expected YYYY/MM/DD/HHh_ticks.bi5: {}", path.display()))?;
+        return Ok(Parsed::Ticks(read_bi5(path, hour_ms, scale)?));
+    }
+    read_csv(path)
+}
+
+/// Start of the hour a Dukascopy file covers, from its `YYYY/MM/DD/HHh_ticks.bi5` path.
+/// Dukascopy months are zero-based.
+fn bi5_hour_start(path: &Path) -> Option<i64> {
+    let parts: Vec<&str> = path
+        .iter()
+        .rev()
+        .take(4)
+        .map(|c| c.to_str())
+        .collect::<Option<_>>()?;
+    let [file, day, month, year] = parts[..] else {
+        return None;
+    };
+    let hour: u32 = file.split('h').next()?.parse().ok()?;
+    let day: u32 = day.parse().ok()?;
+    let month: u32 = month.parse().ok()?;
+    let year: i32 = year.parse().ok()?;
+    let dt = NaiveDate::from_ymd_opt(year, month + 1, day)?.and_hms_opt(hour, 0, 0)?;
+    Some(dt.and_utc().timestamp() * 1000)
+}
+
+/// Decodes one LZMA-compressed hour of 20-byte big-endian records: millisecond offset,
+/// ask, bid (integer prices) and ask/bid volumes.
+fn read_bi5(path: &Path, hour_ms: i64, scale: f64) -> Result<Vec<RawTick>> {
+    let raw = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if raw.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut data = Vec::new();
+    lzma_rs::lzma_decompress(&mut raw.as_slice(), &mut data)
+        .map_err(|e| anyhow::anyhow!("failed to decompress {}: {e:?}", path.display()))?;
+
+    let be = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    Ok(data
+        .chunks_exact(20)
+        .map(|r| RawTick {
+            ts_ms: hour_ms + be(&r[0..4]) as i64,
+            ask: be(&r[4..8]) as f64 / scale,
+            bid: be(&r[8..12]) as f64 / scale,
+        })
+        .filter(|t| t.bid > 0.0 && t.ask >= t.bid)
+        .collect())
+}
+
+/// Reads a CSV of ticks (`bid`/`ask` columns) or bars (`open`/`high`/`low`/`close`). The
+/// first column is either a millisecond timestamp or a Dukascopy `Gmt time` such as
+/// `01.02.2024 00:00:00.000`.
+fn read_csv(path: &Path) -> Result<Parsed> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_path(path)
+        .with_context(|| format!("failed to open csv: {}", path.display()))?;
+    let headers: Vec<String> = rdr
+        .headers()?
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+    let col = |name: &str| headers.iter().position(|h| h == name);
+
+    if let (Some(bid), Some(ask)) = (col("bid"), col("ask")) {
+        let mut ticks = Vec::new();
+        for r in rdr.records().flatten() {
+            let ts = r.get(0).and_then(parse_time_ms);
+            let b = r.get(bid).and_then(|x| x.trim().parse::<f64>().ok());
+            let a = r.get(ask).and_then(|x| x.trim().parse::<f64>().ok());
+            if let (Some(ts_ms), Some(bid), Some(ask)) = (ts, b, a)
+                && bid > 0.0
+                && ask >= bid
+            {
+                ticks.push(RawTick { ts_ms, bid, ask });
+            }
+        }
+        return Ok(Parsed::Ticks(ticks));
+    }
+
+    let (Some(o), Some(h), Some(l), Some(c)) = (col("open"), col("high"), col("low"), col("close"))
+    else {
+        bail!(
+            "{}: expected bid/ask or open/high/low/close columns",
+            path.display()
+        );
+    };
+    let mut bars = Vec::new();
+    for r in rdr.records().flatten() {
+        let num = |i: usize| r.get(i).and_then(|x| x.trim().parse::<f64>().ok());
+        if let (Some(ts_ms), Some(open), Some(high), Some(low), Some(close)) = (
+            r.get(0).and_then(parse_time_ms),
+            num(o),
+            num(h),
+            num(l),
+            num(c),
+        ) {
+            bars.push(Bar {
+                ts: ts_ms.div_euclid(1000),
+                open,
+                high,
+                low,
+                close,
+                spread: col("spread").and_then(num).unwrap_or(f64::NAN),
+            });
+        }
+    }
+    Ok(Parsed::Bars(bars))
+}
+
+fn parse_time_ms(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if let Ok(ms) = s.parse::<i64>() {
+        return Some(ms);
+    }
+    [
+        "%d.%m.%Y %H:%M:%S%.3f",
+        "%d.%m.%Y %H:%M:%S",
+        "%Y-%m-%d %H:%M:%S%.3f",
+    ]
+    .iter()
+    .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+    .map(|dt| dt.and_utc().timestamp_millis())
+}
+
+/// M5 bid bars with the spread (in points) of each bar's last tick.
+fn bars_from_ticks(ticks: &[RawTick], point: f64) -> Vec<Bar> {
+    let mut bars: Vec<Bar> = Vec::new();
+    for t in ticks {
+        let ts = t.ts_ms.div_euclid(1000).div_euclid(BAR_SECONDS) * BAR_SECONDS;
+        let spread = ((t.ask - t.bid) / point).round();
+        match bars.last_mut() {
+            Some(b) if b.ts == ts => {
+                b.high = b.high.max(t.bid);
+                b.low = b.low.min(t.bid);
+                b.close = t.bid;
+                b.spread = spread;
+            }
+            _ => bars.push(Bar {
+                ts,
+                open: t.bid,
+                high: t.bid,
+                low: t.bid,
+                close: t.bid,
+                spread,
+            }),
+        }
+    }
+    bars
+}
+
+/// Folds bars of any period up to five minutes (e.g. M1 exports) into M5 bars.
+pub fn to_m5(mut bars: Vec<Bar>) -> Vec<Bar> {
+    bars.sort_by_key(|b| b.ts);
+    let mut out: Vec<Bar> = Vec::new();
+    for b in bars {
+        let ts = b.ts.div_euclid(BAR_SECONDS) * BAR_SECONDS;
+        match out.last_mut() {
+            Some(m) if m.ts == ts => {
+                m.high = m.high.max(b.high);
+                m.low = m.low.min(b.low);
+                m.close = b.close;
+                if !b.spread.is_nan() {
+                    m.spread = b.spread;
+                }
+            }
+            _ => out.push(Bar { ts, ..b }),
+        }
+    }
+    out
+}
+
+/// `{out_dir}/{symbol}-{kind}-{first day}-{last day}-merged.csv`, the name
+/// `select_merged_file` looks for.
+pub fn merged_path(
+    out_dir: &Path,
+    symbol: &str,
+    kind: &str,
+    first_ts: i64,
+    last_ts: i64,
+) -> Result<PathBuf> {
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("failed to create {}", out_dir.display()))?;
+    let day = |ts: i64| ts_to_utc(ts)[..10].to_string();
+    Ok(out_dir.join(format!(
+        "{}-{}-{}-{}-merged.csv",
+        symbol.to_lowercase(),
+        kind,
+        day(first_ts),
+        day(last_ts)
+    )))
+}
+
+/// Writes bars in the loader's format, with a `spread` column when any bar has one.
+pub fn write_bars(path: &Path, bars: &[Bar], digits: i32) -> Result<()> {
+    let d = digits.max(0) as usize;
+    let with_spread = bars.iter().any(|b| b.spread().is_some());
+    let mut w = BufWriter::new(
+        fs::File::create(path).with_context(|| format!("failed to write {}", path.display()))?,
+    );
+    writeln!(
+        w,
+        "timestamp,open,high,low,close{}",
+        if with_spread { ",spread" } else { "" }
+    )?;
+    for b in bars {
+        write!(
+            w,
+            "{},{:.d$},{:.d$},{:.d$},{:.d$}",
+            b.ts * 1000,
+            b.open,
+            b.high,
+            b.low,
+            b.close
+        )?;
+        match (with_spread, b.spread()) {
+            (true, Some(s)) => writeln!(w, ",{s}")?,
+            (true, None) => writeln!(w, ",")?,
+            _ => writeln!(w)?,
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+fn write_ticks(path: &Path, ticks: &[RawTick], digits: i32) -> Result<()> {
+    let d = digits.max(0) as usize;
+    let mut w = BufWriter::new(
+        fs::File::create(path).with_context(|| format!("failed to write {}", path.display()))?,
+    );
+    writeln!(w, "timestamp,bid,ask")?;
+    for t in ticks {
+        writeln!(w, "{},{:.d$},{:.d$}", t.ts_ms, t.bid, t.ask)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// LZMA-compresses `(ms offset, ask, bid)` records into `ticks.bi5` in a temp
+    /// directory that is removed when dropped.
+    fn bi5_file(records: &[(u32, u32, u32)]) -> TempDir {
+        let mut data = Vec::new();
+        for &(ms, ask, bid) in records {
+            for v in [ms, ask, bid] {
+                data.extend_from_slice(&v.to_be_bytes());
+            }
+            data.extend_from_slice(&1.5_f32.to_be_bytes());
+            data.extend_from_slice(&2.5_f32.to_be_bytes());
+        }
+        let mut packed = Vec::new();
+        lzma_rs::lzma_compress(&mut data.as_slice(), &mut packed).expect("compress");
+        let dir = tempfile::tempdir().expect("create temp dir");
+        fs::write(dir.path().join("ticks.bi5"), packed).expect("write temp file");
+        dir
+    }
+
+    #[test]
+    fn decodes_bi5_records() {
+        let hour_ms = 1_704_067_200_000;
+        let dir = bi5_file(&[
+            (250, 107_262, 107_247),
+            (61_000, 107_270, 107_255),
+            // Crossed quote, dropped.
+            (62_000, 107_200, 107_255),
+        ]);
+        let ticks = read_bi5(&dir.path().join("ticks.bi5"), hour_ms, 100_000.0).unwrap();
+
+        assert_eq!(ticks.len(), 2);
+        assert_eq!(ticks[0].ts_ms, hour_ms + 250);
+        assert!((ticks[0].ask - 1.07262).abs() < 1e-12);
+        assert!((ticks[0].bid - 1.07247).abs() < 1e-12);
+        assert_eq!(ticks[1].ts_ms, hour_ms + 61_000);
+    }
+
+    #[test]
+    fn empty_bi5_is_an_hour_without_ticks() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("empty.bi5");
+        fs::write(&path, b"").expect("write temp file");
+        let ticks = read_bi5(&path, 0, 100_000.0).unwrap();
+        assert!(ticks.is_empty());
+    }
+
+    #[test]
+    fn import_skips_ask_fragments_next_to_bid_fragments() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let input = dir.path().join("download");
+        fs::create_dir(&input).unwrap();
+        let header = "timestamp,open,high,low,close\n";
+        fs::write(
+            input.join("audnzd-m5-ask-2024.csv"),
+            format!("{header}1704067200000,1.10020,1.10040,1.10010,1.10030\n"),
+        )
+        .unwrap();
+        fs::write(
+            input.join("audnzd-m5-bid-2024.csv"),
+            format!("{header}1704067200000,1.10000,1.10020,1.09990,1.10010\n"),
+        )
+        .unwrap();
+        let out_dir = dir.path().join("out");
+        run_import(&ImportArgs {
+            symbol: "AUDNZD".to_string(),
+            input,
+            ticks: false,
+            out_dir: out_dir.clone(),
+            price_scale: None,
+            symbols_file: dir.path().join("symbols.json"),
+        })
+        .unwrap();
+
+        let merged = fs::read_dir(&out_dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let text = fs::read_to_string(merged).unwrap();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                "timestamp,open,high,low,close",
+                "1704067200000,1.10000,1.10020,1.09990,1.10010"
+            ]
+        );
+        assert!(is_ask_side(Path::new(
+            "AUDNZD_Candlestick_5_M_ASK_01.01.2024-31.12.2024.csv"
+        )));
+        assert!(!is_ask_side(Path::new("AUDNZD_Ticks_2024.csv")));
+    }
+
+    #[test]
+    fn bi5_hour_comes_from_the_path_with_zero_based_months() {
+        let path = Path::new("dukascopy/AUDNZD/2024/00/01/13h_ticks.bi5");
+        assert_eq!(bi5_hour_start(path), Some(1_704_114_000_000));
+        assert_eq!(bi5_hour_start(Path::new("13h_ticks.bi5")), None);
+    }
+}
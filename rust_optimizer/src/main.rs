@@ -1,1269 +1,61 @@
 mod amazing31_mt4;
+mod audit;
+mod bar_cache;
+mod constraints;
+mod currency;
+mod execution;
+mod holdout;
+mod import;
+mod merge;
+mod mt4_history;
+mod search_space;
+mod server_time;
+mod sim_broker;
+mod symbol_spec;
+mod tick_data;
+mod timeframe;
+mod walk_forward;
 
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicUsize};
 
 use anyhow::{Context, Result, bail};
 use chrono::{DateTime, Duration, NaiveDate, Utc};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
-use serde::{Deserialize, Serialize};
+use rayon::prelude::*;
+use serde::Serialize;
 use serde_json::{Map, Value, json};
 
+use amazing31_mt4::{Amazing31Mt4, Amazing31Params, BrokerApi};
+use audit::{AuditAction, AuditArgs, AuditOptions, audit_bars, run_audit};
+use bar_cache::Bars;
+use currency::{DEFAULT_ACCOUNT, QuoteConversion};
+use execution::{SlippageModelKind, SpreadModelKind};
+use holdout::{Holdout, evaluate_holdout};
+use import::{ImportArgs, run_import};
+use merge::{MergeArgs, run_merge};
+use mt4_history::{HistoryInfo, is_mt4_history, read_fxt, read_history_bars};
+use search_space::{ParamKind, SearchSpace};
+use server_time::{DstRule, ServerTime};
+use sim_broker::{
+    BAR_SECONDS, Bar, CommissionMode, PricePath, SimBroker, SimConfig, StopOutEvent, StopOutMode,
+    SwapMode,
+};
+use symbol_spec::SymbolSpec;
+use tick_data::{Tick, load_ticks_from_csv, ticks_between, ticks_to_bars};
+use timeframe::{Timeframe, frame_bar_times};
+use walk_forward::{WalkForward, walk_forward};
+
 const INITIAL_BALANCE: f64 = 10_000.0;
 const MAGIC: i32 = 9453;
 const LEVERAGE: i32 = 100;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
-enum OrderType {
-    Buy = 0,
-    Sell = 1,
-    BuyStop = 4,
-    SellStop = 5,
-}
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
-enum OpenMode {
-    Bar = 1,
-    Sleep = 2,
-    Always = 3,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct Order {
-    ticket: i64,
-    symbol: String,
-    magic: i32,
-    order_type: OrderType,
-    lots: f64,
-    open_price: f64,
-    profit: f64,
-    swap: f64,
-    commission: f64,
-    comment: String,
-    open_time: i64,
-}
-
-impl Order {
-    fn total_profit(&self) -> f64 {
-        self.profit + self.swap + self.commission
-    }
-}
-
-#[derive(Clone, Debug)]
-struct Bar {
-    ts: i64,
-    open: f64,
-    high: f64,
-    low: f64,
-    close: f64,
-}
-
-#[derive(Clone, Debug)]
-#[allow(dead_code)]
-struct Config {
-    symbol: String,
-    magic: i32,
-    totals: i32,
-    max_spread: f64,
-    leverage_min: i32,
-
-    close_buy_sell: bool,
-    homeopathy_close_all: bool,
-    homeopathy: bool,
-    over: bool,
-    next_time: i64,
-
-    money: f64,
-    first_step: i32,
-    min_distance: i32,
-    two_min_distance: i32,
-    step_trail_orders: i32,
-    step: i32,
-    two_step: i32,
-
-    open_mode: OpenMode,
-    sleep_seconds: i64,
-
-    max_loss: f64,
-    max_loss_close_all: f64,
-    lot: f64,
-    max_lot: f64,
-    plus_lot: f64,
-    k_lot: f64,
-    digits_lot: i32,
-
-    close_all: f64,
-    profit_by_count: bool,
-    stop_profit: f64,
-    stop_loss: f64,
-
-    on_top_not_buy_first: f64,
-    on_under_not_sell_first: f64,
-    on_top_not_buy_add: f64,
-    on_under_not_sell_add: f64,
-
-    ea_start_time: String,
-    ea_stop_time: String,
-    limit_start_time: String,
-    limit_stop_time: String,
-
-    check_margin_for_add_orders: bool,
-}
-
-impl Config {
-    fn from_params(symbol: &str, params: &Map<String, Value>) -> Self {
-        let mut max_loss = get_f64(params, "max_loss", 100_000.0);
-        let mut max_loss_close_all = get_f64(params, "max_loss_close_all", 50.0);
-        let mut stop_loss = get_f64(params, "stop_loss", 0.0);
-        let mut money = get_f64(params, "money", 0.0);
-
-        if max_loss > 0.0 {
-            max_loss = -max_loss;
-        }
-        if max_loss_close_all > 0.0 {
-            max_loss_close_all = -max_loss_close_all;
-        }
-        if stop_loss > 0.0 {
-            stop_loss = -stop_loss;
-        }
-        if money > 0.0 {
-            money = -money;
-        }
-
-        let open_mode_raw = get_i64(params, "open_mode", 1);
-        let open_mode = match open_mode_raw {
-            1 => OpenMode::Bar,
-            2 => OpenMode::Sleep,
-            3 => OpenMode::Always,
-            _ => OpenMode::Bar,
-        };
-
-        Self {
-            symbol: symbol.to_string(),
-            magic: MAGIC,
-            totals: get_i64(params, "totals", 50) as i32,
-            max_spread: get_f64(params, "max_spread", 32.0),
-            leverage_min: get_i64(params, "leverage_min", 100) as i32,
-
-            close_buy_sell: get_bool(params, "close_buy_sell", true),
-            homeopathy_close_all: get_bool(params, "homeopathy_close_all", true),
-            homeopathy: get_bool(params, "homeopathy", false),
-            over: get_bool(params, "over", false),
-            next_time: get_i64(params, "next_time", 0),
-
-            money,
-            first_step: get_i64(params, "first_step", 30) as i32,
-            min_distance: get_i64(params, "min_distance", 60) as i32,
-            two_min_distance: get_i64(params, "two_min_distance", 60) as i32,
-            step_trail_orders: get_i64(params, "step_trail_orders", 5) as i32,
-            step: get_i64(params, "step", 100) as i32,
-            two_step: get_i64(params, "two_step", 100) as i32,
-
-            open_mode,
-            sleep_seconds: get_i64(params, "sleep_seconds", 30),
-
-            max_loss,
-            max_loss_close_all,
-            lot: get_f64(params, "lot", 0.01),
-            max_lot: get_f64(params, "max_lot", 10.0),
-            plus_lot: get_f64(params, "plus_lot", 0.0),
-            k_lot: get_f64(params, "k_lot", 1.3),
-            digits_lot: get_i64(params, "digits_lot", 3) as i32,
-
-            close_all: get_f64(params, "close_all", 0.5),
-            profit_by_count: get_bool(params, "profit_by_count", true),
-            stop_profit: get_f64(params, "stop_profit", 2.0),
-            stop_loss,
-
-            on_top_not_buy_first: get_f64(params, "on_top_not_buy_first", 0.0),
-            on_under_not_sell_first: get_f64(params, "on_under_not_sell_first", 0.0),
-            on_top_not_buy_add: get_f64(params, "on_top_not_buy_add", 0.0),
-            on_under_not_sell_add: get_f64(params, "on_under_not_sell_add", 0.0),
-
-            ea_start_time: clean_time(get_string(params, "ea_start_time", "00:00")),
-            ea_stop_time: clean_time(get_string(params, "ea_stop_time", "24:00")),
-            limit_start_time: clean_time(get_string(params, "limit_start_time", "00:00")),
-            limit_stop_time: clean_time(get_string(params, "limit_stop_time", "24:00")),
-
-            check_margin_for_add_orders: get_bool(params, "check_margin_for_add_orders", false),
-        }
-    }
-}
-
-fn clean_time(s: String) -> String {
-    let t = s.trim().replace(' ', "");
-    if t == "24:00" {
-        "23:59:59".to_string()
-    } else {
-        t
-    }
-}
-
-#[derive(Clone, Debug)]
-struct State {
-    pause_until: i64,
-    last_bar_time: i64,
-    peak_buy_diff: f64,
-    peak_sell_diff: f64,
-}
-
-impl Default for State {
-    fn default() -> Self {
-        Self {
-            pause_until: 0,
-            last_bar_time: 0,
-            peak_buy_diff: 0.0,
-            peak_sell_diff: 0.0,
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-struct SimBroker {
-    symbol: String,
-    balance: f64,
-    equity: f64,
-    leverage: i32,
-
-    digits: i32,
-    point: f64,
-
-    orders: Vec<Order>,
-    next_ticket: i64,
-
-    current_bar: Option<Bar>,
-    bid: f64,
-    ask: f64,
-    spread_points: f64,
-
-    closed_pnls: Vec<f64>,
-    equity_curve: Vec<f64>,
-    balance_curve: Vec<f64>,
-    spread_pips_curve: Vec<f64>,
-
-    rng: StdRng,
-}
-
-impl SimBroker {
-    fn new(symbol: &str, initial_balance: f64, leverage: i32, seed: u64) -> Self {
-        Self {
-            symbol: symbol.to_string(),
-            balance: initial_balance,
-            equity: initial_balance,
-            leverage,
-            digits: 5,
-            point: 0.00001,
-            orders: Vec::new(),
-            next_ticket: 1,
-            current_bar: None,
-            bid: 0.0,
-            ask: 0.0,
-            spread_points: 0.0,
-            closed_pnls: Vec::new(),
-            equity_curve: Vec::new(),
-            balance_curve: Vec::new(),
-            spread_pips_curve: Vec::new(),
-            rng: StdRng::seed_from_u64(seed),
-        }
-    }
-
-    fn get_orders(&self) -> &[Order] {
-        &self.orders
-    }
-
-    fn get_bid_ask(&self) -> (f64, f64) {
-        (self.bid, self.ask)
-    }
-
-    fn free_margin(&self) -> f64 {
-        self.equity - self.used_margin()
-    }
-
-    fn margin_per_lot(&self, symbol: &str) -> f64 {
-        if symbol != self.symbol {
-            0.0
-        } else {
-            100_000.0 / self.leverage as f64
-        }
-    }
-
-    fn is_trade_allowed(&self) -> bool {
-        true
-    }
-
-    fn is_expert_enabled(&self) -> bool {
-        true
-    }
-
-    fn is_stopped(&self) -> bool {
-        false
-    }
-
-    fn send_pending(
-        &mut self,
-        order_type: OrderType,
-        lots: f64,
-        price: f64,
-        comment: &str,
-    ) -> Option<i64> {
-        let ticket = self.next_ticket;
-        self.next_ticket += 1;
-        self.orders.push(Order {
-            ticket,
-            symbol: self.symbol.clone(),
-            magic: MAGIC,
-            order_type,
-            lots,
-            open_price: round_to(price, self.digits),
-            profit: 0.0,
-            swap: 0.0,
-            commission: 0.0,
-            comment: comment.to_string(),
-            open_time: self.current_bar.as_ref().map(|b| b.ts).unwrap_or(0),
-        });
-        Some(ticket)
-    }
-
-    fn modify_order(&mut self, ticket: i64, new_price: f64) -> bool {
-        if let Some(o) = self.orders.iter_mut().find(|o| o.ticket == ticket) {
-            if matches!(o.order_type, OrderType::BuyStop | OrderType::SellStop) {
-                o.open_price = round_to(new_price, self.digits);
-                return true;
-            }
-        }
-        false
-    }
-
-    fn close_order(&mut self, ticket: i64) -> bool {
-        let Some(idx) = self.orders.iter().position(|o| o.ticket == ticket) else {
-            return false;
-        };
-        let o = self.orders[idx].clone();
-
-        if matches!(o.order_type, OrderType::BuyStop | OrderType::SellStop) {
-            return self.delete_order(ticket);
-        }
-
-        let pnl = match o.order_type {
-            OrderType::Buy => {
-                let close_px = self.apply_slippage_price(self.bid, o.lots, true);
-                self.pnl_buy(o.lots, o.open_price, close_px)
-            }
-            OrderType::Sell => {
-                let close_px = self.apply_slippage_price(self.ask, o.lots, false);
-                self.pnl_sell(o.lots, o.open_price, close_px)
-            }
-            _ => 0.0,
-        };
-
-        self.balance += pnl;
-        self.closed_pnls.push(pnl);
-        self.orders.remove(idx);
-        self.mark_to_market();
-        true
-    }
-
-    fn delete_order(&mut self, ticket: i64) -> bool {
-        let n0 = self.orders.len();
-        self.orders.retain(|o| o.ticket != ticket);
-        self.orders.len() != n0
-    }
-
-    fn on_bar(&mut self, bar: &Bar) {
-        self.current_bar = Some(bar.clone());
-
-        let spread_pips = self.dynamic_spread_pips(bar);
-        self.spread_points = spread_pips * 10.0;
-
-        let half_spread = (self.spread_points * self.point) / 2.0;
-        let mid = bar.close;
-        self.bid = round_to(mid - half_spread, self.digits);
-        self.ask = round_to(mid + half_spread, self.digits);
-        self.spread_pips_curve.push(spread_pips);
-    }
-
-    fn trigger_pending_from_bar(&mut self) {
-        let Some(ohlc) = self.current_bar.clone() else {
-            return;
-        };
-
-        for idx in 0..self.orders.len() {
-            let (order_type, open_price, lots) = {
-                let o = &self.orders[idx];
-                (o.order_type, o.open_price, o.lots)
-            };
-
-            if order_type == OrderType::BuyStop && ohlc.high >= open_price {
-                let base_fill = ohlc.open.max(open_price);
-                let fill = self.apply_slippage_price(base_fill, lots, true);
-                let o = &mut self.orders[idx];
-                o.order_type = OrderType::Buy;
-                o.open_price = round_to(fill, self.digits);
-                o.open_time = ohlc.ts;
-            } else if order_type == OrderType::SellStop && ohlc.low <= open_price {
-                let base_fill = ohlc.open.min(open_price);
-                let fill = self.apply_slippage_price(base_fill, lots, false);
-                let o = &mut self.orders[idx];
-                o.order_type = OrderType::Sell;
-                o.open_price = round_to(fill, self.digits);
-                o.open_time = ohlc.ts;
-            }
-        }
-
-        self.mark_to_market();
-    }
-
-    fn snapshot(&mut self) {
-        self.mark_to_market();
-        self.equity_curve.push(self.equity);
-        self.balance_curve.push(self.balance);
-    }
-
-    fn used_margin(&self) -> f64 {
-        self.orders
-            .iter()
-            .filter(|o| matches!(o.order_type, OrderType::Buy | OrderType::Sell))
-            .map(|o| o.lots * self.margin_per_lot(&self.symbol))
-            .sum()
-    }
-
-    fn mark_to_market(&mut self) {
-        let bid = self.bid;
-        let ask = self.ask;
-        let mut floating = 0.0;
-        for o in &mut self.orders {
-            o.profit = match o.order_type {
-                OrderType::Buy => Self::pnl_buy_calc(o.lots, o.open_price, bid),
-                OrderType::Sell => Self::pnl_sell_calc(o.lots, o.open_price, ask),
-                _ => 0.0,
-            };
-            floating += o.total_profit();
-        }
-        self.equity = self.balance + floating;
-    }
-
-    fn dynamic_spread_pips(&mut self, bar: &Bar) -> f64 {
-        let base = 0.55;
-        let range_pips = ((bar.high - bar.low) / 0.0001).max(0.0);
-        let vol_part = (0.018 * range_pips).min(1.6);
-
-        let hour = DateTime::from_timestamp(bar.ts, 0)
-            .map(|dt| dt.hour() as i32)
-            .unwrap_or(0);
-
-        let session = if hour >= 21 || hour <= 1 {
-            0.45
-        } else if (6..=15).contains(&hour) {
-            0.0
-        } else {
-            0.15
-        };
-
-        let noise = self.rng.random_range(-0.08..=0.12);
-        clamp(base + vol_part + session + noise, 0.25, 3.0)
-    }
-
-    fn apply_slippage_price(&mut self, price: f64, lots: f64, is_buy: bool) -> f64 {
-        let Some(bar) = self.current_bar.as_ref() else {
-            return price;
-        };
-
-        let range_pips = ((bar.high - bar.low) / 0.0001).max(0.0);
-        let vol_component = (0.012 * range_pips).min(1.2);
-        let size_component = ((lots - 0.05).max(0.0) * 0.18).min(0.6);
-        let noise = abs_gauss(&mut self.rng, 0.10);
-        let slip_pips = (0.08 + vol_component + size_component + noise).min(2.5);
-        let slip = slip_pips * 0.0001;
-
-        if is_buy {
-            round_to(price + slip, self.digits)
-        } else {
-            round_to(price - slip, self.digits)
-        }
-    }
-
-    fn pnl_buy(&self, lots: f64, open_price: f64, close_bid: f64) -> f64 {
-        Self::pnl_buy_calc(lots, open_price, close_bid)
-    }
-
-    fn pnl_buy_calc(lots: f64, open_price: f64, close_bid: f64) -> f64 {
-        let units = 100_000.0 * lots;
-        if close_bid <= 0.0 {
-            return 0.0;
-        }
-        units * (close_bid - open_price) / close_bid
-    }
-
-    fn pnl_sell(&self, lots: f64, open_price: f64, close_ask: f64) -> f64 {
-        Self::pnl_sell_calc(lots, open_price, close_ask)
-    }
-
-    fn pnl_sell_calc(lots: f64, open_price: f64, close_ask: f64) -> f64 {
-        let units = 100_000.0 * lots;
-        if close_ask <= 0.0 {
-            return 0.0;
-        }
-        units * (open_price - close_ask) / close_ask
-    }
-}
-
-use chrono::Timelike;
-
-#[derive(Clone, Debug)]
-struct Amazing31 {
-    cfg: Config,
-    state: State,
-}
-
-impl Amazing31 {
-    fn new(cfg: Config) -> Self {
-        Self {
-            cfg,
-            state: State::default(),
-        }
-    }
-
-    fn n(&self, broker: &SimBroker, value: f64) -> f64 {
-        round_to(value, broker.digits)
-    }
-
-    fn orders(&self, broker: &SimBroker) -> Vec<Order> {
-        broker
-            .get_orders()
-            .iter()
-            .filter(|o| o.symbol == self.cfg.symbol && o.magic == self.cfg.magic)
-            .cloned()
-            .collect()
-    }
-
-    fn lizong_10(&self, broker: &SimBroker, order_type: i32, sign_mode: i32, top_n: usize) -> f64 {
-        let mut vals = Vec::new();
-        for o in self.orders(broker) {
-            if order_type != -100 && o.order_type as i32 != order_type {
-                continue;
-            }
-            if sign_mode == 1 && o.profit >= 0.0 {
-                vals.push(o.profit);
-            } else if sign_mode == 2 && o.profit < 0.0 {
-                vals.push(-o.profit);
-            }
-        }
-        vals.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
-        vals.into_iter().take(top_n).sum()
-    }
-
-    fn lizong_9(
-        &mut self,
-        broker: &mut SimBroker,
-        order_type: OrderType,
-        mut count: i32,
-        mode: i32,
-    ) {
-        while count > 0 {
-            let mut pool: Vec<Order> = self
-                .orders(broker)
-                .into_iter()
-                .filter(|o| o.order_type == order_type)
-                .collect();
-            if pool.is_empty() {
-                return;
-            }
-            pool.sort_by(|a, b| {
-                if mode == 1 {
-                    b.profit.partial_cmp(&a.profit).unwrap_or(Ordering::Equal)
-                } else {
-                    a.profit.partial_cmp(&b.profit).unwrap_or(Ordering::Equal)
-                }
-            });
-            let target = pool[0].clone();
-            if mode == 1 && target.profit >= 0.0 {
-                if broker.close_order(target.ticket) {
-                    count -= 1;
-                }
-            } else if mode == 1 && target.profit < 0.0 {
-                count -= 1;
-            } else if mode == 2 && target.profit < 0.0 {
-                if broker.close_order(target.ticket) {
-                    count -= 1;
-                }
-            } else if mode == 2 && target.profit >= 0.0 {
-                count -= 1;
-            } else {
-                return;
-            }
-        }
-    }
-
-    fn lizong_7(&mut self, broker: &mut SimBroker, side: i32) -> bool {
-        for _ in 0..10 {
-            let mut remain = 0;
-            let all = self.orders(broker);
-            for o in all {
-                let ok = if matches!(o.order_type, OrderType::Buy | OrderType::BuyStop)
-                    && (side == 1 || side == 0)
-                {
-                    if o.order_type == OrderType::Buy {
-                        broker.close_order(o.ticket)
-                    } else {
-                        broker.delete_order(o.ticket)
-                    }
-                } else if matches!(o.order_type, OrderType::Sell | OrderType::SellStop)
-                    && (side == -1 || side == 0)
-                {
-                    if o.order_type == OrderType::Sell {
-                        broker.close_order(o.ticket)
-                    } else {
-                        broker.delete_order(o.ticket)
-                    }
-                } else {
-                    continue;
-                };
-                if !ok {
-                    remain += 1;
-                }
-            }
-            if remain == 0 {
-                return true;
-            }
-        }
-        false
-    }
-
-    fn time_to_seconds(value: &str) -> i64 {
-        let parts: Vec<&str> = value.split(':').collect();
-        let (h, m, s) = match parts.len() {
-            2 => (parts[0], parts[1], "0"),
-            3 => (parts[0], parts[1], parts[2]),
-            _ => return 0,
-        };
-
-        let hh = h.parse::<i64>().unwrap_or(0).clamp(0, 23);
-        let mm = m.parse::<i64>().unwrap_or(0).clamp(0, 59);
-        let ss = s.parse::<i64>().unwrap_or(0).clamp(0, 59);
-        hh * 3600 + mm * 60 + ss
-    }
-
-    fn in_time_window(&self, now_ts: i64, start: &str, stop: &str) -> bool {
-        let Some(dt) = DateTime::from_timestamp(now_ts, 0) else {
-            return true;
-        };
-        let cur = dt.hour() as i64 * 3600 + dt.minute() as i64 * 60 + dt.second() as i64;
-        let start_s = Self::time_to_seconds(start);
-        let stop_s = Self::time_to_seconds(stop);
-
-        if start_s <= stop_s {
-            start_s <= cur && cur <= stop_s
-        } else {
-            cur >= start_s || cur <= stop_s
-        }
-    }
-
-    fn count_ss(side_orders: &[Order]) -> i32 {
-        side_orders.iter().filter(|o| o.comment == "SS").count() as i32
-    }
-
-    fn latest_open_time(side_orders: &[Order]) -> i64 {
-        let mut latest_ticket = -1_i64;
-        let mut latest_open = 0_i64;
-        for o in side_orders {
-            if o.ticket > latest_ticket {
-                latest_ticket = o.ticket;
-                latest_open = o.open_time;
-            }
-        }
-        latest_open
-    }
-
-    fn on_tick(&mut self, broker: &mut SimBroker, now_ts: i64, current_bar_ts: i64) {
-        let (bid, ask) = broker.get_bid_ask();
-        let pt = broker.point;
-
-        let orders = self.orders(broker);
-        let buys: Vec<Order> = orders
-            .iter()
-            .filter(|o| o.order_type == OrderType::Buy)
-            .cloned()
-            .collect();
-        let sells: Vec<Order> = orders
-            .iter()
-            .filter(|o| o.order_type == OrderType::Sell)
-            .cloned()
-            .collect();
-        let buystops: Vec<Order> = orders
-            .iter()
-            .filter(|o| o.order_type == OrderType::BuyStop)
-            .cloned()
-            .collect();
-        let sellstops: Vec<Order> = orders
-            .iter()
-            .filter(|o| o.order_type == OrderType::SellStop)
-            .cloned()
-            .collect();
-
-        let buy_profit: f64 = buys.iter().map(Order::total_profit).sum();
-        let sell_profit: f64 = sells.iter().map(Order::total_profit).sum();
-        let total_profit = buy_profit + sell_profit;
-
-        let buy_lots: f64 = buys.iter().map(|o| o.lots).sum();
-        let sell_lots: f64 = sells.iter().map(|o| o.lots).sum();
-
-        let buy_high = buys
-            .iter()
-            .chain(buystops.iter())
-            .map(|o| o.open_price)
-            .fold(0.0_f64, f64::max);
-        let buy_low = buys
-            .iter()
-            .map(|o| o.open_price)
-            .fold(f64::INFINITY, f64::min);
-        let buy_low = if buy_low.is_finite() { buy_low } else { 0.0 };
-
-        let sell_low = sells
-            .iter()
-            .chain(sellstops.iter())
-            .map(|o| o.open_price)
-            .fold(f64::INFINITY, f64::min);
-        let sell_low = if sell_low.is_finite() { sell_low } else { 0.0 };
-        let sell_high = sells.iter().map(|o| o.open_price).fold(0.0_f64, f64::max);
-
-        let buy_ss_count = Self::count_ss(&buys);
-        let sell_ss_count = Self::count_ss(&sells);
-        let sell_ss_when_no_buy_ss = if buy_ss_count < 1 { sell_ss_count } else { 0 };
-
-        let mut can_buy = true;
-        let mut can_sell = true;
-
-        if !self.in_time_window(now_ts, &self.cfg.ea_start_time, &self.cfg.ea_stop_time) {
-            can_buy = false;
-            can_sell = false;
-        }
-
-        if broker.leverage < self.cfg.leverage_min
-            || !broker.is_trade_allowed()
-            || !broker.is_expert_enabled()
-            || broker.is_stopped()
-            || (buys.len() + sells.len()) as i32 >= self.cfg.totals
-            || broker.spread_points > self.cfg.max_spread
-        {
-            can_buy = false;
-            can_sell = false;
-        }
-
-        if now_ts < self.state.pause_until {
-            can_buy = false;
-            can_sell = false;
-        }
-
-        if self.cfg.over && buys.is_empty() {
-            can_buy = false;
-        }
-        if self.cfg.over && sells.is_empty() {
-            can_sell = false;
-        }
-
-        if self.cfg.over && total_profit >= self.cfg.close_all {
-            self.lizong_7(broker, 0);
-            if self.cfg.next_time > 0 {
-                self.state.pause_until = now_ts + self.cfg.next_time;
-            }
-            return;
-        }
-
-        if !self.cfg.over {
-            if (sell_ss_when_no_buy_ss < 1 || !self.cfg.homeopathy_close_all)
-                && buy_profit > self.cfg.max_loss_close_all
-                && sell_profit > self.cfg.max_loss_close_all
-            {
-                if (self.cfg.profit_by_count
-                    && buy_profit > self.cfg.stop_profit * buys.len() as f64)
-                    || (!self.cfg.profit_by_count && buy_profit > self.cfg.stop_profit)
-                {
-                    self.lizong_7(broker, 1);
-                    return;
-                }
-                if (self.cfg.profit_by_count
-                    && sell_profit > self.cfg.stop_profit * sells.len() as f64)
-                    || (!self.cfg.profit_by_count && sell_profit > self.cfg.stop_profit)
-                {
-                    self.lizong_7(broker, -1);
-                    return;
-                }
-            }
-
-            if self.cfg.homeopathy_close_all
-                && (buy_ss_count > 0 || sell_ss_count > 0)
-                && total_profit >= self.cfg.close_all
-            {
-                self.lizong_7(broker, 0);
-                if self.cfg.next_time > 0 {
-                    self.state.pause_until = now_ts + self.cfg.next_time;
-                }
-                return;
-            }
-
-            if total_profit >= self.cfg.close_all
-                && (buy_profit <= self.cfg.max_loss_close_all
-                    || sell_profit <= self.cfg.max_loss_close_all)
-            {
-                self.lizong_7(broker, 0);
-                if self.cfg.next_time > 0 {
-                    self.state.pause_until = now_ts + self.cfg.next_time;
-                }
-                return;
-            }
-        }
-
-        if self.cfg.stop_loss != 0.0 && total_profit <= self.cfg.stop_loss {
-            self.lizong_7(broker, 0);
-            if self.cfg.next_time > 0 {
-                self.state.pause_until = now_ts + self.cfg.next_time;
-            }
-            return;
-        }
-
-        if self.cfg.close_buy_sell {
-            let buy_diff = self.lizong_10(broker, OrderType::Buy as i32, 1, 1)
-                - self.lizong_10(broker, OrderType::Buy as i32, 2, 2);
-            self.state.peak_buy_diff = self.state.peak_buy_diff.max(buy_diff);
-            if self.state.peak_buy_diff > 0.0 && buy_diff > 0.0 && buy_lots > 0.0 && buys.len() > 3
-            {
-                let best_buy = buys.iter().map(|o| o.profit).fold(0.0_f64, f64::max);
-                let best_buy_lot = buys
-                    .iter()
-                    .find(|o| (o.profit - best_buy).abs() < 1e-12)
-                    .map(|o| o.lots)
-                    .unwrap_or(0.0);
-                if buy_lots > best_buy_lot * 3.0 + sell_lots {
-                    self.lizong_9(broker, OrderType::Buy, 1, 1);
-                    self.lizong_9(broker, OrderType::Buy, 2, 2);
-                    self.state.peak_buy_diff = 0.0;
-                    self.state.peak_sell_diff = 0.0;
-                }
-            }
-
-            let sell_diff = self.lizong_10(broker, OrderType::Sell as i32, 1, 1)
-                - self.lizong_10(broker, OrderType::Sell as i32, 2, 2);
-            self.state.peak_sell_diff = self.state.peak_sell_diff.max(sell_diff);
-            if self.state.peak_sell_diff > 0.0
-                && sell_diff > 0.0
-                && sell_lots > 0.0
-                && sells.len() > 3
-            {
-                let best_sell = sells.iter().map(|o| o.profit).fold(0.0_f64, f64::max);
-                let best_sell_lot = sells
-                    .iter()
-                    .find(|o| (o.profit - best_sell).abs() < 1e-12)
-                    .map(|o| o.lots)
-                    .unwrap_or(0.0);
-                if sell_lots > best_sell_lot * 3.0 + buy_lots {
-                    self.lizong_9(broker, OrderType::Sell, 1, 1);
-                    self.lizong_9(broker, OrderType::Sell, 2, 2);
-                    self.state.peak_buy_diff = 0.0;
-                    self.state.peak_sell_diff = 0.0;
-                }
-            }
-        }
-
-        let aggressive_mode = self.cfg.money == 0.0 || total_profit > self.cfg.money;
-
-        let open_gate = (self.cfg.open_mode == OpenMode::Bar
-            && self.state.last_bar_time != current_bar_ts)
-            || matches!(self.cfg.open_mode, OpenMode::Sleep | OpenMode::Always);
-
-        if open_gate {
-            let buy_last_open = Self::latest_open_time(&buys);
-            let sell_last_open = Self::latest_open_time(&sells);
-            let limit_window = self.in_time_window(
-                now_ts,
-                &self.cfg.limit_start_time,
-                &self.cfg.limit_stop_time,
-            );
-
-            self.try_open_buy(
-                broker,
-                now_ts,
-                can_buy,
-                ask,
-                pt,
-                &buys,
-                &buystops,
-                buy_profit,
-                buy_lots,
-                sell_lots,
-                buy_high,
-                buy_low,
-                aggressive_mode,
-                buy_last_open,
-                limit_window,
-            );
-            self.try_open_sell(
-                broker,
-                now_ts,
-                can_sell,
-                bid,
-                pt,
-                &sells,
-                &sellstops,
-                sell_profit,
-                sell_lots,
-                buy_lots,
-                sell_low,
-                sell_high,
-                aggressive_mode,
-                sell_last_open,
-                limit_window,
-            );
-
-            self.state.last_bar_time = current_bar_ts;
-        }
-
-        self.trail_pending_buy(
-            broker,
-            can_buy,
-            ask,
-            pt,
-            buys.len() as i32,
-            aggressive_mode,
-            buy_high,
-            buy_low,
-            &buystops,
-        );
-        self.trail_pending_sell(
-            broker,
-            can_sell,
-            bid,
-            pt,
-            sells.len() as i32,
-            aggressive_mode,
-            sell_low,
-            sell_high,
-            &sellstops,
-        );
-    }
-
-    fn calc_lot(&self, side_count: i32) -> f64 {
-        let x = if side_count == 0 {
-            self.cfg.lot
-        } else {
-            self.cfg.lot * self.cfg.k_lot.powi(side_count) + side_count as f64 * self.cfg.plus_lot
-        };
-        round_to(x.min(self.cfg.max_lot), self.cfg.digits_lot)
-    }
-
-    fn can_afford(&self, broker: &SimBroker, lots: f64) -> bool {
-        if !self.cfg.check_margin_for_add_orders {
-            return true;
-        }
-        let need = broker.margin_per_lot(&self.cfg.symbol);
-        if need <= 0.0 {
-            return true;
-        }
-        lots * 2.0 < broker.free_margin() / need
-    }
-
-    #[allow(clippy::too_many_arguments)]
-    fn try_open_buy(
-        &mut self,
-        broker: &mut SimBroker,
-        now_ts: i64,
-        can_buy: bool,
-        ask: f64,
-        pt: f64,
-        buys: &[Order],
-        buystops: &[Order],
-        buy_profit: f64,
-        buy_lots: f64,
-        sell_lots: f64,
-        buy_high: f64,
-        buy_low: f64,
-        aggressive_mode: bool,
-        last_open_time: i64,
-        limit_window: bool,
-    ) {
-        if !buystops.is_empty() || buy_profit <= self.cfg.max_loss || !can_buy {
-            return;
-        }
-        if self.cfg.open_mode == OpenMode::Sleep && now_ts - last_open_time < self.cfg.sleep_seconds
-        {
-            return;
-        }
-
-        let count = buys.len() as i32;
-        let px = if count == 0 {
-            self.n(broker, ask + self.cfg.first_step as f64 * pt)
-        } else {
-            let base = if aggressive_mode {
-                self.cfg.min_distance
-            } else {
-                self.cfg.two_min_distance
-            };
-            let step = if aggressive_mode {
-                self.cfg.step
-            } else {
-                self.cfg.two_step
-            };
-            let mut px = self.n(broker, ask + base as f64 * pt);
-            if buy_low > 0.0 && px < self.n(broker, buy_low - step as f64 * pt) {
-                px = self.n(broker, ask + step as f64 * pt);
-            }
-            px
-        };
-
-        let step_now = if aggressive_mode {
-            self.cfg.step
-        } else {
-            self.cfg.two_step
-        };
-
-        let cond = count == 0
-            || (buy_high > 0.0
-                && px >= self.n(broker, buy_high + step_now as f64 * pt)
-                && sell_lots > buy_lots * 3.0
-                && sell_lots - buy_lots > 0.2)
-            || (buy_low > 0.0 && px <= self.n(broker, buy_low - step_now as f64 * pt))
-            || (self.cfg.homeopathy
-                && buy_high > 0.0
-                && px >= self.n(broker, buy_high + self.cfg.step as f64 * pt)
-                && (buy_lots - sell_lots).abs() < 1e-12);
-
-        if !cond {
-            return;
-        }
-
-        let lots = self.calc_lot(count);
-        if count > 0 && !self.can_afford(broker, lots) {
-            return;
-        }
-
-        if count > 0
-            && limit_window
-            && self.cfg.on_top_not_buy_add != 0.0
-            && px >= self.cfg.on_top_not_buy_add
-        {
-            return;
-        }
-
-        let ss_comment = (buy_high > 0.0
-            && px >= self.n(broker, buy_high + step_now as f64 * pt)
-            && sell_lots > buy_lots * 3.0
-            && sell_lots - buy_lots > 0.2)
-            || (self.cfg.homeopathy
-                && buy_high > 0.0
-                && px >= self.n(broker, buy_high + self.cfg.step as f64 * pt)
-                && (buy_lots - sell_lots).abs() < 1e-12);
-
-        let comment = if ss_comment { "SS" } else { "NN" };
-        broker.send_pending(OrderType::BuyStop, lots, px, comment);
-    }
-
-    #[allow(clippy::too_many_arguments)]
-    fn try_open_sell(
-        &mut self,
-        broker: &mut SimBroker,
-        now_ts: i64,
-        can_sell: bool,
-        bid: f64,
-        pt: f64,
-        sells: &[Order],
-        sellstops: &[Order],
-        sell_profit: f64,
-        sell_lots: f64,
-        buy_lots: f64,
-        sell_low: f64,
-        sell_high: f64,
-        aggressive_mode: bool,
-        last_open_time: i64,
-        limit_window: bool,
-    ) {
-        if !sellstops.is_empty() || sell_profit <= self.cfg.max_loss || !can_sell {
-            return;
-        }
-        if self.cfg.open_mode == OpenMode::Sleep && now_ts - last_open_time < self.cfg.sleep_seconds
-        {
-            return;
-        }
-
-        let count = sells.len() as i32;
-        let px = if count == 0 {
-            self.n(broker, bid - self.cfg.first_step as f64 * pt)
-        } else {
-            let base = if aggressive_mode {
-                self.cfg.min_distance
-            } else {
-                self.cfg.two_min_distance
-            };
-            let step = if aggressive_mode {
-                self.cfg.step
-            } else {
-                self.cfg.two_step
-            };
-            let mut px = self.n(broker, bid - base as f64 * pt);
-            if sell_high > 0.0 && px < self.n(broker, sell_high + step as f64 * pt) {
-                px = self.n(broker, bid - step as f64 * pt);
-            }
-            px
-        };
-
-        let step_now = if aggressive_mode {
-            self.cfg.step
-        } else {
-            self.cfg.two_step
-        };
-
-        let cond = count == 0
-            || (sell_low > 0.0
-                && px <= self.n(broker, sell_low - step_now as f64 * pt)
-                && buy_lots > sell_lots * 3.0
-                && buy_lots - sell_lots > 0.2)
-            || (sell_high > 0.0 && px >= self.n(broker, sell_high + step_now as f64 * pt))
-            || (self.cfg.homeopathy
-                && sell_low > 0.0
-                && px <= self.n(broker, sell_low - self.cfg.step as f64 * pt)
-                && (buy_lots - sell_lots).abs() < 1e-12);
-
-        if !cond {
-            return;
-        }
-
-        let lots = self.calc_lot(count);
-        if count > 0 && !self.can_afford(broker, lots) {
-            return;
-        }
-
-        if count > 0
-            && limit_window
-            && self.cfg.on_under_not_sell_add != 0.0
-            && px <= self.cfg.on_under_not_sell_add
-        {
-            return;
-        }
-
-        let ss_comment = (sell_low > 0.0
-            && px <= self.n(broker, sell_low - step_now as f64 * pt)
-            && buy_lots > sell_lots * 3.0
-            && buy_lots - sell_lots > 0.2)
-            || (self.cfg.homeopathy
-                && sell_low > 0.0
-                && px <= self.n(broker, sell_low - self.cfg.step as f64 * pt)
-                && (buy_lots - sell_lots).abs() < 1e-12);
-
-        let comment = if ss_comment { "SS" } else { "NN" };
-        broker.send_pending(OrderType::SellStop, lots, px, comment);
-    }
-
-    #[allow(clippy::too_many_arguments)]
-    fn trail_pending_buy(
-        &mut self,
-        broker: &mut SimBroker,
-        can_buy: bool,
-        ask: f64,
-        pt: f64,
-        buy_count: i32,
-        aggressive_mode: bool,
-        buy_high: f64,
-        buy_low: f64,
-        buystops: &[Order],
-    ) {
-        if !can_buy || buystops.is_empty() {
-            return;
-        }
-
-        let pending = buystops
-            .iter()
-            .max_by(|a, b| {
-                a.open_price
-                    .partial_cmp(&b.open_price)
-                    .unwrap_or(Ordering::Equal)
-            })
-            .unwrap();
-
-        let base = if buy_count == 0 {
-            self.cfg.first_step
-        } else if aggressive_mode {
-            self.cfg.min_distance
-        } else {
-            self.cfg.two_min_distance
-        };
-        let px = self.n(broker, ask + base as f64 * pt);
-
-        if self.n(
-            broker,
-            pending.open_price - self.cfg.step_trail_orders as f64 * pt,
-        ) > px
-        {
-            let step = if aggressive_mode {
-                self.cfg.step
-            } else {
-                self.cfg.two_step
-            };
-            let cond = buy_low == 0.0
-                || px <= self.n(broker, buy_low - step as f64 * pt)
-                || px >= self.n(broker, buy_high + step as f64 * pt);
-            if cond {
-                broker.modify_order(pending.ticket, px);
-            }
-        }
-    }
-
-    #[allow(clippy::too_many_arguments)]
-    fn trail_pending_sell(
-        &mut self,
-        broker: &mut SimBroker,
-        can_sell: bool,
-        bid: f64,
-        pt: f64,
-        sell_count: i32,
-        aggressive_mode: bool,
-        sell_low: f64,
-        sell_high: f64,
-        sellstops: &[Order],
-    ) {
-        if !can_sell || sellstops.is_empty() {
-            return;
-        }
-
-        let pending = sellstops
-            .iter()
-            .min_by(|a, b| {
-                a.open_price
-                    .partial_cmp(&b.open_price)
-                    .unwrap_or(Ordering::Equal)
-            })
-            .unwrap();
-
-        let base = if sell_count == 0 {
-            self.cfg.first_step
-        } else if aggressive_mode {
-            self.cfg.min_distance
-        } else {
-            self.cfg.two_min_distance
-        };
-        let px = self.n(broker, bid - base as f64 * pt);
-
-        if self.n(
-            broker,
-            pending.open_price + self.cfg.step_trail_orders as f64 * pt,
-        ) < px
-        {
-            let step = if aggressive_mode {
-                self.cfg.step
-            } else {
-                self.cfg.two_step
-            };
-            let cond = sell_high == 0.0
-                || px >= self.n(broker, sell_high + step as f64 * pt)
-                || px <= self.n(broker, sell_low - step as f64 * pt);
-            if cond {
-                broker.modify_order(pending.ticket, px);
-            }
-        }
-    }
-}
-
 #[derive(Clone, Debug, Serialize)]
 struct YearResult {
     year_idx: usize,
@@ -1273,49 +65,106 @@ struct YearResult {
     net_profit: f64,
     final_balance: f64,
     max_drawdown_pct: f64,
+    worst_intrabar_drawdown_pct: f64,
     min_free_margin: f64,
+    total_swap: f64,
+    total_commission: f64,
+    margin_calls: usize,
+    stop_outs: usize,
+    rejected_requests: usize,
+    close_alerts: usize,
+    last_close_error: Option<i32>,
+    order_errors: usize,
+    last_order_error: Option<i32>,
     blew_up: bool,
     dd_limit_hit: bool,
     stop_time_utc: String,
+    stop_out_events: Vec<StopOutEvent>,
+    /// Equity at the last bar of each server day, keyed by that bar's server time.
+    #[serde(skip)]
+    daily_equity: Vec<(i64, f64)>,
 }
 
 fn run_one_year(
     year_idx: usize,
     bars: &[Bar],
-    cfg: &Config,
+    symbol: &str,
+    params: &Amazing31Params,
+    sim: &SimConfig,
     drawdown_limit: f64,
     seed: u64,
 ) -> YearResult {
     let mut broker = SimBroker::new(
-        &cfg.symbol,
+        sim,
+        symbol,
         INITIAL_BALANCE,
         LEVERAGE,
         seed + year_idx as u64,
     );
-    let mut strat = Amazing31::new(cfg.clone());
+    let mut strat = Amazing31Mt4::new(params.clone());
 
     let mut blew_up = false;
     let mut dd_limit_hit = false;
     let mut stop_ts = 0_i64;
     let mut min_free_margin = f64::INFINITY;
+    let mut daily_equity: Vec<(i64, f64)> = Vec::new();
 
-    for bar in bars {
+    // `OpenMode == 1` opens once per bar of the EA's `TimeZone` period.
+    let tf = Timeframe::from_mql(params.TimeZone).unwrap_or(Timeframe::M5);
+    let frames = frame_bar_times(bars, tf);
+
+    for (bar, &frame_ts) in bars.iter().zip(&frames) {
+        // Below M5 only the quotes inside the bar can tell the frame bars apart.
+        let frame_at = |ts: i64| {
+            if tf == Timeframe::M1 {
+                tf.bar_start(ts)
+            } else {
+                frame_ts
+            }
+        };
         broker.on_bar(bar);
-        broker.trigger_pending_from_bar();
-        strat.on_tick(&mut broker, bar.ts, bar.ts);
+        match broker.price_path() {
+            PricePath::Close => {
+                broker.trigger_pending_from_bar();
+                strat.start(&mut broker, bar.ts, frame_ts);
+            }
+            PricePath::Ticks => {
+                for t in ticks_between(&sim.ticks, bar.ts, bar.ts + BAR_SECONDS) {
+                    // The broker and the EA see whole seconds, as MT4's TimeCurrent does.
+                    let ts = t.ts();
+                    broker.on_quote(ts, t.bid, t.ask);
+                    strat.start(&mut broker, ts, frame_at(ts));
+                }
+            }
+            PricePath::Ohlc | PricePath::ControlPoints => {
+                for (ts, mid) in broker.bar_ticks(bar) {
+                    broker.on_tick(ts, mid);
+                    strat.start(&mut broker, ts, frame_at(ts));
+                }
+            }
+        }
         broker.snapshot();
+        match daily_equity.last_mut() {
+            Some(last) if last.0.div_euclid(86_400) == bar.ts.div_euclid(86_400) => {
+                *last = (bar.ts, broker.equity);
+            }
+            _ => daily_equity.push((bar.ts, broker.equity)),
+        }
 
-        let eq = broker.equity;
-        let fm = broker.free_margin();
+        let eq = broker.bar_worst_equity();
+        let fm = broker.bar_worst_free_margin();
         min_free_margin = min_free_margin.min(fm);
 
-        if eq <= 0.0 || fm <= 0.0 {
+        // With stop-out emulation on, the broker liquidates before free margin runs out and
+        // only a negative equity ends the year; without it, fall back to the free-margin test.
+        if eq <= 0.0 || (sim.stop_out_level <= 0.0 && fm <= 0.0) {
             blew_up = true;
             stop_ts = bar.ts;
             break;
         }
 
-        let max_dd = calc_max_drawdown(&broker.equity_curve) * 100.0;
+        let max_dd =
+            calc_intrabar_drawdown(&broker.equity_curve, &broker.worst_equity_curve) * 100.0;
         if max_dd >= drawdown_limit {
             dd_limit_hit = true;
             stop_ts = bar.ts;
@@ -1323,27 +172,17 @@ fn run_one_year(
         }
     }
 
-    let close_list: Vec<(i64, OrderType)> = broker
-        .get_orders()
-        .iter()
-        .map(|o| (o.ticket, o.order_type))
-        .collect();
-    for (ticket, typ) in close_list {
-        match typ {
-            OrderType::Buy | OrderType::Sell => {
-                broker.close_order(ticket);
-            }
-            OrderType::BuyStop | OrderType::SellStop => {
-                broker.delete_order(ticket);
-            }
-        }
-    }
+    broker.close_all();
     broker.snapshot();
+    if let Some(last) = daily_equity.last_mut() {
+        last.1 = broker.equity;
+    }
 
-    let start_utc = ts_to_utc(bars.first().map(|x| x.ts).unwrap_or(0));
-    let end_utc = ts_to_utc(bars.last().map(|x| x.ts).unwrap_or(0));
+    let clock = &sim.server_time;
+    let start_utc = ts_to_utc(clock.to_utc(bars.first().map(|x| x.ts).unwrap_or(0)));
+    let end_utc = ts_to_utc(clock.to_utc(bars.last().map(|x| x.ts).unwrap_or(0)));
     let stop_time_utc = if stop_ts > 0 {
-        ts_to_utc(stop_ts)
+        ts_to_utc(clock.to_utc(stop_ts))
     } else {
         "-".to_string()
     };
@@ -1351,6 +190,8 @@ fn run_one_year(
     let final_balance = broker.balance;
     let net_profit = final_balance - INITIAL_BALANCE;
     let max_dd_pct = calc_max_drawdown(&broker.equity_curve) * 100.0;
+    let intrabar_dd_pct =
+        calc_intrabar_drawdown(&broker.equity_curve, &broker.worst_equity_curve) * 100.0;
 
     if !min_free_margin.is_finite() {
         min_free_margin = broker.free_margin();
@@ -1364,30 +205,66 @@ fn run_one_year(
         net_profit,
         final_balance,
         max_drawdown_pct: max_dd_pct,
+        worst_intrabar_drawdown_pct: intrabar_dd_pct,
         min_free_margin,
+        total_swap: broker.swap_total,
+        total_commission: broker.commission_total,
+        margin_calls: broker.margin_call_count(),
+        stop_outs: broker.stop_out_count(),
+        rejected_requests: broker.rejected_requests,
+        close_alerts: strat.close_alerts(),
+        last_close_error: strat.last_close_error().map(|e| e.code()),
+        order_errors: strat.order_errors(),
+        last_order_error: strat.last_order_error().map(|e| e.code()),
         blew_up,
         dd_limit_hit,
         stop_time_utc,
+        stop_out_events: broker.stop_out_events,
+        daily_equity,
     }
 }
 
 fn evaluate_params(
     params: &Map<String, Value>,
-    yearly_bars: &[Vec<Bar>],
+    yearly_bars: &[&[Bar]],
     symbol: &str,
+    sim: &SimConfig,
     drawdown_limit: f64,
 ) -> (f64, Vec<YearResult>, Value) {
-    let cfg = Config::from_params(symbol, params);
-
-    let mut results = Vec::new();
-    for (i, bars) in yearly_bars.iter().enumerate() {
-        let r = run_one_year(i + 1, bars, &cfg, drawdown_limit, 20260226);
-        let stop = r.blew_up || r.dd_limit_hit;
-        results.push(r);
-        if stop {
-            break;
-        }
-    }
+    let strategy_params = strategy_params_from_map(params);
+
+    // Years run in parallel, but the result stops at the first blow-up or drawdown-limit hit
+    // as if they had run in order. A year after a known failure is skipped; every year up to
+    // the first failure always runs, so the kept years do not depend on scheduling.
+    let first_stop = AtomicUsize::new(usize::MAX);
+    let years: Vec<Option<YearResult>> = yearly_bars
+        .par_iter()
+        .enumerate()
+        .map(|(i, bars)| {
+            if first_stop.load(atomic::Ordering::Relaxed) < i {
+                return None;
+            }
+            let r = run_one_year(
+                i + 1,
+                bars,
+                symbol,
+                &strategy_params,
+                sim,
+                drawdown_limit,
+                20260226,
+            );
+            if r.blew_up || r.dd_limit_hit {
+                first_stop.fetch_min(i, atomic::Ordering::Relaxed);
+            }
+            Some(r)
+        })
+        .collect();
+    let kept = first_stop.into_inner().saturating_add(1);
+    let results: Vec<YearResult> = years
+        .into_iter()
+        .take(kept)
+        .map(|r| r.expect("years up to the first failure always run"))
+        .collect();
 
     let nets: Vec<f64> = results.iter().map(|r| r.net_profit).collect();
     let sum_net: f64 = nets.iter().sum();
@@ -1401,16 +278,25 @@ fn evaluate_params(
     } else {
         nets.iter().copied().fold(f64::INFINITY, f64::min)
     };
-    let worst_dd = results
+    let worst_close_dd = results
         .iter()
         .map(|r| r.max_drawdown_pct)
         .fold(0.0_f64, f64::max);
+    let worst_dd = results
+        .iter()
+        .map(|r| r.worst_intrabar_drawdown_pct)
+        .fold(0.0_f64, f64::max);
     let min_free_margin = results
         .iter()
         .map(|r| r.min_free_margin)
         .fold(f64::INFINITY, f64::min);
 
     let blowups = results.iter().filter(|r| r.blew_up).count() as f64;
+    let stop_outs: usize = results.iter().map(|r| r.stop_outs).sum();
+    let stop_out_years = results.iter().filter(|r| r.stop_outs > 0).count() as f64;
+    let rejected: usize = results.iter().map(|r| r.rejected_requests).sum();
+    let close_alerts: usize = results.iter().map(|r| r.close_alerts).sum();
+    let order_errors: usize = results.iter().map(|r| r.order_errors).sum();
     let dd_hits = results.iter().filter(|r| r.dd_limit_hit).count() as f64;
     let years_ran = results.len() as f64;
 
@@ -1436,9 +322,15 @@ fn evaluate_params(
         "avg_net_profit": avg_net,
         "min_year_net_profit": min_net,
         "blowup_years": blowups,
+        "stop_out_years": stop_out_years,
+        "total_stop_outs": stop_outs,
+        "total_rejected_requests": rejected,
+        "total_close_alerts": close_alerts,
+        "total_order_errors": order_errors,
         "dd_limit_hit_years": dd_hits,
         "years_ran": years_ran,
-        "worst_year_max_drawdown_pct": worst_dd,
+        "worst_year_max_drawdown_pct": worst_close_dd,
+        "worst_year_intrabar_drawdown_pct": worst_dd,
         "min_free_margin": if min_free_margin.is_finite() { min_free_margin } else { 0.0 },
         "drawdown_limit_pct": drawdown_limit,
         "feasible_drawdown_limit": if feasible { 1.0 } else { 0.0 },
@@ -1447,6 +339,67 @@ fn evaluate_params(
     (score, results, agg)
 }
 
+/// Maps the optimizer's snake_case parameter map onto the MQ4 extern inputs.
+fn strategy_params_from_map(params: &Map<String, Value>) -> Amazing31Params {
+    let d = Amazing31Params::default();
+    Amazing31Params {
+        On_top_of_this_price_not_Buy_first_order: get_f64(
+            params,
+            "on_top_not_buy_first",
+            d.On_top_of_this_price_not_Buy_first_order,
+        ),
+        On_under_of_this_price_not_Sell_first_order: get_f64(
+            params,
+            "on_under_not_sell_first",
+            d.On_under_of_this_price_not_Sell_first_order,
+        ),
+        On_top_of_this_price_not_Buy_order: get_f64(
+            params,
+            "on_top_not_buy_add",
+            d.On_top_of_this_price_not_Buy_order,
+        ),
+        On_under_of_this_price_not_Sell_order: get_f64(
+            params,
+            "on_under_not_sell_add",
+            d.On_under_of_this_price_not_Sell_order,
+        ),
+        Limit_StartTime: get_string(params, "limit_start_time", &d.Limit_StartTime),
+        Limit_StopTime: get_string(params, "limit_stop_time", &d.Limit_StopTime),
+        CloseBuySell: get_bool(params, "close_buy_sell", d.CloseBuySell),
+        HomeopathyCloseAll: get_bool(params, "homeopathy_close_all", d.HomeopathyCloseAll),
+        Homeopathy: get_bool(params, "homeopathy", d.Homeopathy),
+        Over: get_bool(params, "over", d.Over),
+        NextTime: get_i64(params, "next_time", d.NextTime),
+        Money: get_f64(params, "money", d.Money),
+        FirstStep: get_i64(params, "first_step", d.FirstStep as i64) as i32,
+        MinDistance: get_i64(params, "min_distance", d.MinDistance as i64) as i32,
+        TwoMinDistance: get_i64(params, "two_min_distance", d.TwoMinDistance as i64) as i32,
+        StepTrallOrders: get_i64(params, "step_trail_orders", d.StepTrallOrders as i64) as i32,
+        Step: get_i64(params, "step", d.Step as i64) as i32,
+        TwoStep: get_i64(params, "two_step", d.TwoStep as i64) as i32,
+        OpenMode: get_i64(params, "open_mode", d.OpenMode as i64) as i32,
+        TimeZone: get_i64(params, "time_zone", d.TimeZone as i64) as i32,
+        sleep: get_i64(params, "sleep_seconds", d.sleep),
+        MaxLoss: get_f64(params, "max_loss", d.MaxLoss),
+        MaxLossCloseAll: get_f64(params, "max_loss_close_all", d.MaxLossCloseAll),
+        lot: get_f64(params, "lot", d.lot),
+        Maxlot: get_f64(params, "max_lot", d.Maxlot),
+        PlusLot: get_f64(params, "plus_lot", d.PlusLot),
+        K_Lot: get_f64(params, "k_lot", d.K_Lot),
+        DigitsLot: get_i64(params, "digits_lot", d.DigitsLot as i64) as i32,
+        CloseAll: get_f64(params, "close_all", d.CloseAll),
+        Profit: get_bool(params, "profit_by_count", d.Profit),
+        StopProfit: get_f64(params, "stop_profit", d.StopProfit),
+        StopLoss: get_f64(params, "stop_loss", d.StopLoss),
+        Magic: MAGIC,
+        Totals: get_i64(params, "totals", d.Totals as i64) as i32,
+        MaxSpread: get_f64(params, "max_spread", d.MaxSpread),
+        Leverage: get_i64(params, "leverage_min", d.Leverage as i64) as i32,
+        EA_StartTime: get_string(params, "ea_start_time", &d.EA_StartTime),
+        EA_StopTime: get_string(params, "ea_stop_time", &d.EA_StopTime),
+    }
+}
+
 fn calc_max_drawdown(equity_curve: &[f64]) -> f64 {
     if equity_curve.is_empty() {
         return 0.0;
@@ -1467,17 +420,67 @@ fn calc_max_drawdown(equity_curve: &[f64]) -> f64 {
     max_dd
 }
 
+/// Drawdown of each bar's intrabar equity low against the highest close-to-close equity seen
+/// before it. `worst_curve[i]` must be the intrabar low matching `equity_curve[i]`.
+fn calc_intrabar_drawdown(equity_curve: &[f64], worst_curve: &[f64]) -> f64 {
+    if equity_curve.is_empty() {
+        return 0.0;
+    }
+    let mut peak = equity_curve[0];
+    let mut max_dd = 0.0;
+    for (&e, &w) in equity_curve.iter().zip(worst_curve) {
+        if peak > 0.0 {
+            let dd = (peak - w.min(e)) / peak;
+            if dd > max_dd {
+                max_dd = dd;
+            }
+        }
+        if e > peak {
+            peak = e;
+        }
+    }
+    max_dd
+}
+
+/// Reads bars from a CSV or an MT4 `.hst`/`.fxt` history file, with the number of rows that
+/// could not be parsed and, for MT4 files, what the file says about the symbol.
+fn read_bars_file(path: &Path) -> Result<(Vec<Bar>, usize, Option<HistoryInfo>)> {
+    if is_mt4_history(path) {
+        let (info, bars) = read_history_bars(path)?;
+        return Ok((bars, 0, Some(info)));
+    }
+    let (bars, skipped) = read_bars_csv(path)?;
+    Ok((bars, skipped, None))
+}
+
 fn load_bars_from_csv(path: &Path) -> Result<Vec<Bar>> {
+    read_bars_csv(path).map(|(bars, _)| bars)
+}
+
+/// Reads `timestamp,open,high,low,close` rows with millisecond timestamps. An optional
+/// `spread` column (in points, as MT4 exports it) is kept per bar. Also returns how many
+/// rows could not be parsed.
+fn read_bars_csv(path: &Path) -> Result<(Vec<Bar>, usize)> {
     let mut rdr = csv::ReaderBuilder::new()
         .has_headers(true)
+        .flexible(true)
         .from_path(path)
         .with_context(|| format!("failed to open csv: {}", path.display()))?;
+    let spread_col = rdr
+        .headers()
+        .with_context(|| format!("failed to read csv header: {}", path.display()))?
+        .iter()
+        .position(|h| h.trim().eq_ignore_ascii_case("spread"));
 
     let mut bars = Vec::new();
+    let mut skipped = 0;
     for rec in rdr.records() {
         let r = match rec {
             Ok(x) => x,
-            Err(_) => continue,
+            Err(_) => {
+                skipped += 1;
+                continue;
+            }
         };
         let ts = r
             .get(0)
@@ -1487,6 +490,11 @@ fn load_bars_from_csv(path: &Path) -> Result<Vec<Bar>> {
         let h = r.get(2).and_then(|x| x.parse::<f64>().ok());
         let l = r.get(3).and_then(|x| x.parse::<f64>().ok());
         let c = r.get(4).and_then(|x| x.parse::<f64>().ok());
+        let spread = spread_col
+            .and_then(|i| r.get(i))
+            .and_then(|x| x.trim().parse::<f64>().ok())
+            .filter(|x| *x >= 0.0)
+            .unwrap_or(f64::NAN);
         if let (Some(ts), Some(open), Some(high), Some(low), Some(close)) = (ts, o, h, l, c) {
             bars.push(Bar {
                 ts,
@@ -1494,14 +502,44 @@ fn load_bars_from_csv(path: &Path) -> Result<Vec<Bar>> {
                 high,
                 low,
                 close,
+                spread,
             });
+        } else {
+            skipped += 1;
         }
     }
     bars.sort_by_key(|b| b.ts);
-    Ok(bars)
+    Ok((bars, skipped))
+}
+
+/// The `-ask-` file next to a `-bid-` data file, if there is one.
+fn paired_ask_file(bid_file: &Path) -> Option<PathBuf> {
+    let name = bid_file.file_name()?.to_str()?;
+    if !name.contains("-bid-") {
+        return None;
+    }
+    let ask = bid_file.with_file_name(name.replacen("-bid-", "-ask-", 1));
+    ask.exists().then_some(ask)
+}
+
+/// Sets each bid bar's spread (in points) from the ask bar with the same timestamp, unless
+/// the bid file already recorded one. Returns how many bars were matched.
+fn apply_ask_spread(bars: &mut [Bar], ask_bars: &[Bar], point: f64) -> usize {
+    let mut matched = 0;
+    for b in bars.iter_mut().filter(|b| b.spread.is_nan()) {
+        let Ok(i) = ask_bars.binary_search_by_key(&b.ts, |a| a.ts) else {
+            continue;
+        };
+        let spread = ((ask_bars[i].close - b.close) / point).round();
+        if spread >= 0.0 {
+            b.spread = spread;
+            matched += 1;
+        }
+    }
+    matched
 }
 
-fn split_into_year_windows(bars: &[Bar], years: usize) -> Vec<Vec<Bar>> {
+fn split_into_year_windows(bars: &[Bar], years: usize) -> Vec<&[Bar]> {
     if bars.is_empty() {
         return Vec::new();
     }
@@ -1519,7 +557,7 @@ fn split_into_year_windows(bars: &[Bar], years: usize) -> Vec<Vec<Bar>> {
         if j <= idx {
             break;
         }
-        windows.push(bars[idx..j].to_vec());
+        windows.push(&bars[idx..j]);
         idx = j;
         start_ts = end_ts;
     }
@@ -1531,9 +569,9 @@ fn load_or_select_data(
     symbol: &str,
     years: usize,
     data_file: &Option<PathBuf>,
-) -> Result<(PathBuf, Vec<Bar>)> {
-    let end_d = Utc::now().date_naive();
-    let start_d = end_d - Duration::days(365 * years as i64);
+    use_cache: bool,
+) -> Result<(PathBuf, Bars, usize, Option<HistoryInfo>)> {
+    let (start_d, end_d, start_ts, end_ts) = lookback_range(years);
 
     let selected = if let Some(p) = data_file {
         p.clone()
@@ -1541,11 +579,93 @@ fn load_or_select_data(
         select_merged_file(symbol, start_d, end_d)?
     };
 
-    let all_bars = load_bars_from_csv(&selected)?;
-    if all_bars.is_empty() {
-        bail!("data is empty: {}", selected.display());
+    let (bars, skipped, info) = if use_cache && !is_mt4_history(&selected) {
+        let (bars, skipped) = Bars::load_cached(&selected, start_ts, end_ts)?;
+        (bars, skipped, None)
+    } else {
+        let (all_bars, skipped, info) = read_bars_file(&selected)?;
+        if all_bars.is_empty() {
+            bail!("data is empty: {}", selected.display());
+        }
+        let bars: Vec<Bar> = all_bars
+            .into_iter()
+            .filter(|b| b.ts >= start_ts && b.ts < end_ts)
+            .collect();
+        (bars.into(), skipped, info)
+    };
+
+    if bars.is_empty() {
+        bail!(
+            "filtered bars is empty for {} from {} to {}",
+            selected.display(),
+            start_d,
+            end_d
+        );
+    }
+
+    Ok((selected, bars, skipped, info))
+}
+
+/// Loads a tick CSV or MT4 `.fxt` file for the last `years` years and the M5 bid bars
+/// built from it.
+fn load_tick_data(path: &Path, years: usize) -> Result<(Vec<Tick>, Vec<Bar>, Option<HistoryInfo>)> {
+    let (start_d, end_d, start_ts, end_ts) = lookback_range(years);
+
+    let (all_ticks, info) = if is_mt4_history(path) {
+        let (info, ticks) = read_fxt(path)?;
+        (ticks, Some(info))
+    } else {
+        (load_ticks_from_csv(path)?, None)
+    };
+    if all_ticks.is_empty() {
+        bail!("tick data is empty: {}", path.display());
+    }
+    let ticks = ticks_between(&all_ticks, start_ts, end_ts).to_vec();
+    if ticks.is_empty() {
+        bail!(
+            "filtered ticks is empty for {} from {} to {}",
+            path.display(),
+            start_d,
+            end_d
+        );
+    }
+
+    let bars = ticks_to_bars(&ticks);
+    Ok((ticks, bars, info))
+}
+
+/// Takes the digits recorded in an MT4 history file over the spec, since they are what the
+/// prices were quoted in.
+fn apply_history_info(spec: &mut SymbolSpec, info: &HistoryInfo, symbol: &str) {
+    println!(
+        "history symbol={} period=M{} digits={}",
+        info.symbol, info.period, info.digits
+    );
+    if !info
+        .symbol
+        .to_uppercase()
+        .starts_with(&symbol.to_uppercase())
+    {
+        println!(
+            "warning: history file is for {}, not {}",
+            info.symbol, symbol
+        );
+    }
+    if info.digits > 0 && info.digits != spec.digits {
+        println!(
+            "digits {} -> {} from history file",
+            spec.digits, info.digits
+        );
+        spec.digits = info.digits;
+        spec.point = 10f64.powi(-info.digits);
+        spec.tick_size = spec.point;
     }
+}
 
+/// The last `years` years up to today, as dates and as `[start_ts, end_ts)` timestamps.
+fn lookback_range(years: usize) -> (NaiveDate, NaiveDate, i64, i64) {
+    let end_d = Utc::now().date_naive();
+    let start_d = end_d - Duration::days(365 * years as i64);
     let start_ts = start_d
         .and_hms_opt(0, 0, 0)
         .expect("valid start hms")
@@ -1556,22 +676,7 @@ fn load_or_select_data(
         .expect("valid end hms")
         .and_utc()
         .timestamp();
-
-    let bars: Vec<Bar> = all_bars
-        .into_iter()
-        .filter(|b| b.ts >= start_ts && b.ts < end_ts)
-        .collect();
-
-    if bars.is_empty() {
-        bail!(
-            "filtered bars is empty for {} from {} to {}",
-            selected.display(),
-            start_d,
-            end_d
-        );
-    }
-
-    Ok((selected, bars))
+    (start_d, end_d, start_ts, end_ts)
 }
 
 fn select_merged_file(symbol: &str, start_d: NaiveDate, end_d: NaiveDate) -> Result<PathBuf> {
@@ -1633,31 +738,6 @@ fn select_merged_file(symbol: &str, start_d: NaiveDate, end_d: NaiveDate) -> Res
     bail!("no merged file found in download/ for symbol {symbol}")
 }
 
-#[allow(dead_code)]
-#[derive(Clone, Copy, Debug)]
-enum ParamKind {
-    Int {
-        low: i64,
-        high: i64,
-        step: i64,
-    },
-    Float {
-        low: f64,
-        high: f64,
-        step: f64,
-        precision: u32,
-    },
-    Bool {
-        p_true: f64,
-    },
-}
-
-#[derive(Clone, Copy, Debug)]
-struct ParamSpec {
-    name: &'static str,
-    kind: ParamKind,
-}
-
 #[derive(Clone, Debug)]
 struct CandidateEval {
     score: f64,
@@ -1666,114 +746,62 @@ struct CandidateEval {
     years: Vec<YearResult>,
 }
 
-fn param_specs() -> Vec<ParamSpec> {
-    vec![
-        ParamSpec {
-            name: "step",
-            kind: ParamKind::Int {
-                low: 70,
-                high: 340,
-                step: 5,
-            },
-        },
-        ParamSpec {
-            name: "lot",
-            kind: ParamKind::Float {
-                low: 0.005,
-                high: 0.10,
-                step: 0.001,
-                precision: 3,
-            },
-        },
-        ParamSpec {
-            name: "k_lot",
-            kind: ParamKind::Float {
-                low: 1.05,
-                high: 1.45,
-                step: 0.001,
-                precision: 3,
-            },
-        },
-    ]
-}
+type NumericBounds = HashMap<String, (f64, f64)>;
+type BoolProbs = HashMap<String, f64>;
 
-fn base_numeric_bounds(specs: &[ParamSpec]) -> HashMap<&'static str, (f64, f64)> {
+fn base_numeric_bounds(space: &SearchSpace) -> NumericBounds {
     let mut out = HashMap::new();
-    for s in specs {
+    for s in &space.params {
         match s.kind {
             ParamKind::Int { low, high, .. } => {
-                out.insert(s.name, (low as f64, high as f64));
+                out.insert(s.name.clone(), (low as f64, high as f64));
             }
             ParamKind::Float { low, high, .. } => {
-                out.insert(s.name, (low, high));
+                out.insert(s.name.clone(), (low, high));
             }
-            ParamKind::Bool { .. } => {}
+            ParamKind::Bool { .. } | ParamKind::Categorical { .. } => {}
         }
     }
     out
 }
 
-fn base_bool_probs(specs: &[ParamSpec]) -> HashMap<&'static str, f64> {
+fn base_bool_probs(space: &SearchSpace) -> BoolProbs {
     let mut out = HashMap::new();
-    for s in specs {
+    for s in &space.params {
         if let ParamKind::Bool { p_true } = s.kind {
-            out.insert(s.name, p_true);
+            out.insert(s.name.clone(), p_true);
         }
     }
     out
 }
 
-fn seed_candidates() -> Vec<Map<String, Value>> {
-    let mut base = Map::new();
-    base.insert("step".to_string(), Value::from(100));
-    base.insert("lot".to_string(), Value::from(0.01));
-    base.insert("k_lot".to_string(), Value::from(1.3));
-
-    let mut s1 = base.clone();
-    s1.insert("step".to_string(), Value::from(130));
-    s1.insert("lot".to_string(), Value::from(0.02));
-    s1.insert("k_lot".to_string(), Value::from(1.18));
-
-    let mut s2 = base.clone();
-    s2.insert("step".to_string(), Value::from(80));
-    s2.insert("lot".to_string(), Value::from(0.008));
-    s2.insert("k_lot".to_string(), Value::from(1.35));
-
-    let mut s3 = base.clone();
-    s3.insert("step".to_string(), Value::from(180));
-    s3.insert("lot".to_string(), Value::from(0.015));
-    s3.insert("k_lot".to_string(), Value::from(1.10));
-
-    vec![base, s1, s2, s3]
-}
-
 fn sample_candidate(
-    specs: &[ParamSpec],
+    space: &SearchSpace,
     rng: &mut StdRng,
-    num_bounds: &HashMap<&'static str, (f64, f64)>,
-    bool_probs: &HashMap<&'static str, f64>,
+    num_bounds: &NumericBounds,
+    bool_probs: &BoolProbs,
 ) -> Map<String, Value> {
     let mut p = Map::new();
 
-    for s in specs {
+    for s in &space.params {
         match s.kind {
             ParamKind::Bool { p_true } => {
-                let prob = *bool_probs.get(s.name).unwrap_or(&p_true);
+                let prob = *bool_probs.get(&s.name).unwrap_or(&p_true);
                 p.insert(
-                    s.name.to_string(),
+                    s.name.clone(),
                     Value::from(rng.random_bool(prob.clamp(0.02, 0.98))),
                 );
             }
             ParamKind::Int { low, high, step } => {
                 let (l, h) = num_bounds
-                    .get(s.name)
+                    .get(&s.name)
                     .copied()
                     .unwrap_or((low as f64, high as f64));
                 let li = quantize_i64(l.round() as i64, low, high, step);
                 let hi = quantize_i64(h.round() as i64, low, high, step).max(li);
                 let steps = ((hi - li) / step).max(0);
                 let k = rng.random_range(0..=steps);
-                p.insert(s.name.to_string(), Value::from(li + k * step));
+                p.insert(s.name.clone(), Value::from(li + k * step));
             }
             ParamKind::Float {
                 low,
@@ -1781,7 +809,7 @@ fn sample_candidate(
                 step,
                 precision,
             } => {
-                let (l, h) = num_bounds.get(s.name).copied().unwrap_or((low, high));
+                let (l, h) = num_bounds.get(&s.name).copied().unwrap_or((low, high));
                 let mut v = if s.name == "money" {
                     if rng.random_bool(0.85) {
                         0.0
@@ -1792,28 +820,32 @@ fn sample_candidate(
                     rng.random_range(l..=h)
                 };
                 v = quantize_f64(v, low, high, step, precision);
-                p.insert(s.name.to_string(), Value::from(v));
+                p.insert(s.name.clone(), Value::from(v));
+            }
+            ParamKind::Categorical { ref values } => {
+                let v = values[rng.random_range(0..values.len())].clone();
+                p.insert(s.name.clone(), v);
             }
         }
     }
 
-    add_fixed_params(&mut p);
-    repair_candidate(&mut p, specs);
+    space.apply_fixed(&mut p);
+    repair_candidate(&mut p, space);
     p
 }
 
 fn mutate_candidate(
     base: &Map<String, Value>,
-    specs: &[ParamSpec],
+    space: &SearchSpace,
     rng: &mut StdRng,
-    num_bounds: &HashMap<&'static str, (f64, f64)>,
-    bool_probs: &HashMap<&'static str, f64>,
+    num_bounds: &NumericBounds,
+    bool_probs: &BoolProbs,
     scale: f64,
 ) -> Map<String, Value> {
     let mut p = base.clone();
     let mut changed = false;
 
-    for s in specs {
+    for s in &space.params {
         let p_mut = (0.10 + 0.20 * scale).min(0.85);
         if !rng.random_bool(p_mut) {
             continue;
@@ -1821,34 +853,34 @@ fn mutate_candidate(
 
         match s.kind {
             ParamKind::Bool { p_true } => {
-                let cur = get_bool(&p, s.name, false);
-                let prob = *bool_probs.get(s.name).unwrap_or(&p_true);
+                let cur = get_bool(&p, &s.name, false);
+                let prob = *bool_probs.get(&s.name).unwrap_or(&p_true);
                 let v = if rng.random_bool(0.5) {
                     !cur
                 } else {
                     rng.random_bool(prob.clamp(0.02, 0.98))
                 };
-                p.insert(s.name.to_string(), Value::from(v));
+                p.insert(s.name.clone(), Value::from(v));
                 changed = true;
             }
             ParamKind::Int { low, high, step } => {
                 let (l, h) = num_bounds
-                    .get(s.name)
+                    .get(&s.name)
                     .copied()
                     .unwrap_or((low as f64, high as f64));
                 let li = quantize_i64(l.round() as i64, low, high, step);
                 let hi = quantize_i64(h.round() as i64, low, high, step).max(li);
-                let cur = get_i64(&p, s.name, li);
+                let cur = get_i64(&p, &s.name, li);
                 if rng.random_bool((0.05 * scale).min(0.35)) {
                     let steps = ((hi - li) / step).max(0);
                     let k = rng.random_range(0..=steps);
-                    p.insert(s.name.to_string(), Value::from(li + k * step));
+                    p.insert(s.name.clone(), Value::from(li + k * step));
                 } else {
                     let step_count = ((hi - li) / step).max(1) as f64;
                     let max_jump = (step_count * 0.20 * scale).round().max(1.0) as i64;
                     let delta_steps = rng.random_range(-max_jump..=max_jump);
                     let v = quantize_i64(cur + delta_steps * step, li, hi, step);
-                    p.insert(s.name.to_string(), Value::from(v));
+                    p.insert(s.name.clone(), Value::from(v));
                 }
                 changed = true;
             }
@@ -1858,8 +890,8 @@ fn mutate_candidate(
                 step,
                 precision,
             } => {
-                let (l, h) = num_bounds.get(s.name).copied().unwrap_or((low, high));
-                let cur = get_f64(&p, s.name, l);
+                let (l, h) = num_bounds.get(&s.name).copied().unwrap_or((low, high));
+                let cur = get_f64(&p, &s.name, l);
                 let v = if rng.random_bool((0.06 * scale).min(0.30)) {
                     rng.random_range(l..=h)
                 } else {
@@ -1868,32 +900,37 @@ fn mutate_candidate(
                     cur + delta
                 };
                 p.insert(
-                    s.name.to_string(),
+                    s.name.clone(),
                     Value::from(quantize_f64(v, low, high, step, precision)),
                 );
                 changed = true;
             }
+            ParamKind::Categorical { ref values } => {
+                let v = values[rng.random_range(0..values.len())].clone();
+                p.insert(s.name.clone(), v);
+                changed = true;
+            }
         }
     }
 
     if !changed {
-        let idx = rng.random_range(0..specs.len());
-        let s = specs[idx];
+        let idx = rng.random_range(0..space.params.len());
+        let s = &space.params[idx];
         match s.kind {
             ParamKind::Bool { p_true } => {
-                let prob = *bool_probs.get(s.name).unwrap_or(&p_true);
-                p.insert(s.name.to_string(), Value::from(rng.random_bool(prob)));
+                let prob = *bool_probs.get(&s.name).unwrap_or(&p_true);
+                p.insert(s.name.clone(), Value::from(rng.random_bool(prob)));
             }
             ParamKind::Int { low, high, step } => {
                 let (l, h) = num_bounds
-                    .get(s.name)
+                    .get(&s.name)
                     .copied()
                     .unwrap_or((low as f64, high as f64));
                 let li = quantize_i64(l.round() as i64, low, high, step);
                 let hi = quantize_i64(h.round() as i64, low, high, step).max(li);
                 let steps = ((hi - li) / step).max(0);
                 let k = rng.random_range(0..=steps);
-                p.insert(s.name.to_string(), Value::from(li + k * step));
+                p.insert(s.name.clone(), Value::from(li + k * step));
             }
             ParamKind::Float {
                 low,
@@ -1901,48 +938,49 @@ fn mutate_candidate(
                 step,
                 precision,
             } => {
-                let (l, h) = num_bounds.get(s.name).copied().unwrap_or((low, high));
+                let (l, h) = num_bounds.get(&s.name).copied().unwrap_or((low, high));
                 let v = quantize_f64(rng.random_range(l..=h), low, high, step, precision);
-                p.insert(s.name.to_string(), Value::from(v));
+                p.insert(s.name.clone(), Value::from(v));
+            }
+            ParamKind::Categorical { ref values } => {
+                let v = values[rng.random_range(0..values.len())].clone();
+                p.insert(s.name.clone(), v);
             }
         }
     }
 
-    add_fixed_params(&mut p);
-    repair_candidate(&mut p, specs);
+    space.apply_fixed(&mut p);
+    repair_candidate(&mut p, space);
     p
 }
 
 fn crossover_candidate(
     a: &Map<String, Value>,
     b: &Map<String, Value>,
-    specs: &[ParamSpec],
+    space: &SearchSpace,
     rng: &mut StdRng,
 ) -> Map<String, Value> {
     let mut out = Map::new();
-    for s in specs {
+    for s in &space.params {
         let from_a = rng.random_bool(0.5);
         let val = if from_a {
-            a.get(s.name).cloned().unwrap_or(Value::Null)
+            a.get(&s.name).cloned().unwrap_or(Value::Null)
         } else {
-            b.get(s.name).cloned().unwrap_or(Value::Null)
+            b.get(&s.name).cloned().unwrap_or(Value::Null)
         };
-        out.insert(s.name.to_string(), val);
+        out.insert(s.name.clone(), val);
     }
-    add_fixed_params(&mut out);
-    repair_candidate(&mut out, specs);
+    space.apply_fixed(&mut out);
+    repair_candidate(&mut out, space);
     out
 }
 
 fn derive_refined_bounds(
-    specs: &[ParamSpec],
-    base_num: &HashMap<&'static str, (f64, f64)>,
-    base_bool: &HashMap<&'static str, f64>,
+    space: &SearchSpace,
+    base_num: &NumericBounds,
+    base_bool: &BoolProbs,
     source: &[CandidateEval],
-) -> (
-    HashMap<&'static str, (f64, f64)>,
-    HashMap<&'static str, f64>,
-) {
+) -> (NumericBounds, BoolProbs) {
     if source.is_empty() {
         return (base_num.clone(), base_bool.clone());
     }
@@ -1953,28 +991,28 @@ fn derive_refined_bounds(
     let mut num = base_num.clone();
     let mut bp = base_bool.clone();
 
-    for s in specs {
+    for s in &space.params {
         match s.kind {
             ParamKind::Bool { .. } => {
                 let mut c_true = 0.0;
                 for cand in top {
-                    if get_bool(&cand.params, s.name, false) {
+                    if get_bool(&cand.params, &s.name, false) {
                         c_true += 1.0;
                     }
                 }
                 let p = (c_true / top_n as f64).clamp(0.1, 0.9);
-                bp.insert(s.name, p);
+                bp.insert(s.name.clone(), p);
             }
             ParamKind::Int { low, high, step } => {
                 let vals: Vec<f64> = top
                     .iter()
-                    .map(|cand| get_i64(&cand.params, s.name, low) as f64)
+                    .map(|cand| get_i64(&cand.params, &s.name, low) as f64)
                     .collect();
                 let vmin = vals.iter().copied().fold(f64::INFINITY, f64::min);
                 let vmax = vals.iter().copied().fold(f64::NEG_INFINITY, f64::max);
 
                 let (base_l, base_h) = base_num
-                    .get(s.name)
+                    .get(&s.name)
                     .copied()
                     .unwrap_or((low as f64, high as f64));
                 let base_w = (base_h - base_l).max(1.0);
@@ -1987,19 +1025,19 @@ fn derive_refined_bounds(
                     nl = clamp(mid - step as f64, base_l, base_h);
                     nh = clamp(mid + step as f64, base_l, base_h);
                 }
-                num.insert(s.name, (nl, nh));
+                num.insert(s.name.clone(), (nl, nh));
             }
             ParamKind::Float {
                 low, high, step, ..
             } => {
                 let vals: Vec<f64> = top
                     .iter()
-                    .map(|cand| get_f64(&cand.params, s.name, low))
+                    .map(|cand| get_f64(&cand.params, &s.name, low))
                     .collect();
                 let vmin = vals.iter().copied().fold(f64::INFINITY, f64::min);
                 let vmax = vals.iter().copied().fold(f64::NEG_INFINITY, f64::max);
 
-                let (base_l, base_h) = base_num.get(s.name).copied().unwrap_or((low, high));
+                let (base_l, base_h) = base_num.get(&s.name).copied().unwrap_or((low, high));
                 let base_w = (base_h - base_l).max(step);
                 let w = (vmax - vmin).max(step);
                 let pad = (w * 0.25).max(base_w * 0.08);
@@ -2010,25 +1048,57 @@ fn derive_refined_bounds(
                     nl = clamp(mid - step, base_l, base_h);
                     nh = clamp(mid + step, base_l, base_h);
                 }
-                num.insert(s.name, (nl, nh));
+                num.insert(s.name.clone(), (nl, nh));
             }
+            ParamKind::Categorical { .. } => {}
         }
     }
 
     (num, bp)
 }
 
+/// Local-search candidates drawn and evaluated together.
+const LOCAL_BATCH: usize = 8;
+
+fn evaluate_batch(
+    cands: Vec<Map<String, Value>>,
+    yearly_bars: &[&[Bar]],
+    symbol: &str,
+    sim: &SimConfig,
+    drawdown_limit: f64,
+) -> Vec<CandidateEval> {
+    cands
+        .into_par_iter()
+        .map(|params| {
+            let (score, years, agg) =
+                evaluate_params(&params, yearly_bars, symbol, sim, drawdown_limit);
+            CandidateEval {
+                score,
+                params,
+                agg,
+                years,
+            }
+        })
+        .collect()
+}
+
 fn optimize_params(
-    specs: &[ParamSpec],
+    space: &SearchSpace,
     symbol: &str,
-    yearly_bars: &[Vec<Bar>],
+    yearly_bars: &[&[Bar]],
+    sim: &SimConfig,
     drawdown_limit: f64,
     trials: usize,
     seed: u64,
-) -> (CandidateEval, Option<CandidateEval>, Value) {
+) -> Result<(
+    CandidateEval,
+    Option<CandidateEval>,
+    Vec<CandidateEval>,
+    Value,
+)> {
     let mut rng = StdRng::seed_from_u64(seed);
-    let base_num = base_numeric_bounds(specs);
-    let base_bool = base_bool_probs(specs);
+    let base_num = base_numeric_bounds(space);
+    let base_bool = base_bool_probs(space);
 
     let global_trials = (trials / 3).max(10).min(trials);
     let local_trials = trials.saturating_sub(global_trials);
@@ -2041,34 +1111,42 @@ fn optimize_params(
 
     let mut eval_count = 0_usize;
     let mut feasible_count = 0_usize;
+    let mut pruned = 0_usize;
 
-    let mut seeds: VecDeque<Map<String, Value>> = seed_candidates().into();
+    let mut seeds: VecDeque<Map<String, Value>> = space.seeds.clone().into();
 
-    while eval_count < global_trials {
+    // Global candidates do not depend on results, so they are all drawn first and
+    // evaluated together.
+    let mut global_cands = Vec::with_capacity(global_trials);
+    let mut attempts = 0;
+    while global_cands.len() < global_trials && attempts < global_trials * 1000 {
+        attempts += 1;
         let mut cand = if let Some(seed_p) = seeds.pop_front() {
             seed_p
         } else {
-            sample_candidate(specs, &mut rng, &base_num, &base_bool)
+            sample_candidate(space, &mut rng, &base_num, &base_bool)
         };
-        add_fixed_params(&mut cand);
-        repair_candidate(&mut cand, specs);
+        space.apply_fixed(&mut cand);
+        repair_candidate(&mut cand, space);
+        if !space.satisfies(&cand) {
+            pruned += 1;
+            continue;
+        }
 
         let fp = fingerprint_params(&cand);
         if visited.contains(&fp) {
             continue;
         }
         visited.insert(fp);
+        global_cands.push(cand);
+    }
+    if global_cands.is_empty() {
+        bail!("no candidate satisfies the search space constraints");
+    }
 
-        let (score, years, agg) = evaluate_params(&cand, yearly_bars, symbol, drawdown_limit);
+    for ce in evaluate_batch(global_cands, yearly_bars, symbol, sim, drawdown_limit) {
         eval_count += 1;
 
-        let ce = CandidateEval {
-            score,
-            params: cand,
-            agg,
-            years,
-        };
-
         if best_any
             .as_ref()
             .map(|x| ce.score > x.score)
@@ -2097,7 +1175,7 @@ fn optimize_params(
             global_trials,
             ce.score,
             agg_num(&ce.agg, "sum_net_profit"),
-            agg_num(&ce.agg, "worst_year_max_drawdown_pct"),
+            agg_num(&ce.agg, "worst_year_intrabar_drawdown_pct"),
             if is_feasible(&ce.agg) { 1 } else { 0 },
             agg_num(&ce.agg, "years_ran"),
             yearly_bars.len()
@@ -2109,189 +1187,154 @@ fn optimize_params(
     } else {
         top_all.clone()
     };
-    let (ref_num, ref_bool) = derive_refined_bounds(specs, &base_num, &base_bool, &bound_source);
+    let (ref_num, ref_bool) = derive_refined_bounds(space, &base_num, &base_bool, &bound_source);
 
     println!("\nRefined bounds generated from top candidates.");
 
     let mut sigma = 1.0_f64;
     let mut stagnation = 0_i32;
 
-    for i in 1..=local_trials {
-        let mut generated = None;
-        for _ in 0..50 {
-            let parent_pool = if !top_feasible.is_empty() && rng.random_bool(0.75) {
-                &top_feasible
-            } else {
-                &top_all
-            };
+    let mut next_trial = 1;
+    while next_trial <= local_trials {
+        let batch_end = (next_trial + LOCAL_BATCH - 1).min(local_trials);
+        let mut batch = Vec::new();
+        let mut batch_trials = Vec::new();
+        // Candidates of a batch are all drawn from the search state left by the previous
+        // batch. The batch size is fixed so a seed gives the same search on any thread count.
+        for i in next_trial..=batch_end {
+            let mut generated = None;
+            for _ in 0..50 {
+                let parent_pool = if !top_feasible.is_empty() && rng.random_bool(0.75) {
+                    &top_feasible
+                } else {
+                    &top_all
+                };
 
-            let mut cand = if parent_pool.is_empty() || rng.random_bool(0.24) {
-                sample_candidate(specs, &mut rng, &ref_num, &ref_bool)
-            } else {
-                let p1 = select_parent(parent_pool, &mut rng).expect("parent exists");
-                if parent_pool.len() >= 2 && rng.random_bool(0.30) {
-                    let p2 = select_parent(parent_pool, &mut rng).expect("parent exists");
-                    let cross = crossover_candidate(&p1.params, &p2.params, specs, &mut rng);
-                    mutate_candidate(&cross, specs, &mut rng, &ref_num, &ref_bool, sigma)
+                let mut cand = if parent_pool.is_empty() || rng.random_bool(0.24) {
+                    sample_candidate(space, &mut rng, &ref_num, &ref_bool)
                 } else {
-                    mutate_candidate(&p1.params, specs, &mut rng, &ref_num, &ref_bool, sigma)
+                    let p1 = select_parent(parent_pool, &mut rng).expect("parent exists");
+                    if parent_pool.len() >= 2 && rng.random_bool(0.30) {
+                        let p2 = select_parent(parent_pool, &mut rng).expect("parent exists");
+                        let cross = crossover_candidate(&p1.params, &p2.params, space, &mut rng);
+                        mutate_candidate(&cross, space, &mut rng, &ref_num, &ref_bool, sigma)
+                    } else {
+                        mutate_candidate(&p1.params, space, &mut rng, &ref_num, &ref_bool, sigma)
+                    }
+                };
+
+                if rng.random_bool(0.08) {
+                    cand = mutate_candidate(
+                        &cand,
+                        space,
+                        &mut rng,
+                        &ref_num,
+                        &ref_bool,
+                        (sigma * 1.4).min(2.5),
+                    );
+                }
+                if !space.satisfies(&cand) {
+                    pruned += 1;
+                    continue;
                 }
-            };
 
-            if rng.random_bool(0.08) {
-                cand = mutate_candidate(
-                    &cand,
-                    specs,
-                    &mut rng,
-                    &ref_num,
-                    &ref_bool,
-                    (sigma * 1.4).min(2.5),
-                );
+                let fp = fingerprint_params(&cand);
+                if visited.contains(&fp) {
+                    continue;
+                }
+                visited.insert(fp);
+                generated = Some(cand);
+                break;
             }
 
-            let fp = fingerprint_params(&cand);
-            if visited.contains(&fp) {
-                continue;
+            if let Some(cand) = generated {
+                batch.push(cand);
+                batch_trials.push(i);
             }
-            visited.insert(fp);
-            generated = Some(cand);
-            break;
         }
+        next_trial = batch_end + 1;
 
-        let Some(cand) = generated else {
-            continue;
-        };
-
-        let (score, years, agg) = evaluate_params(&cand, yearly_bars, symbol, drawdown_limit);
-        eval_count += 1;
-
-        let ce = CandidateEval {
-            score,
-            params: cand,
-            agg,
-            years,
-        };
-
-        let mut improved = false;
-
-        if best_any
-            .as_ref()
-            .map(|x| ce.score > x.score)
-            .unwrap_or(true)
-        {
-            best_any = Some(ce.clone());
-            improved = true;
-        }
+        let evals = evaluate_batch(batch, yearly_bars, symbol, sim, drawdown_limit);
+        for (i, ce) in batch_trials.into_iter().zip(evals) {
+            eval_count += 1;
+            let mut improved = false;
 
-        if is_feasible(&ce.agg) {
-            feasible_count += 1;
-            if best_feasible
+            if best_any
                 .as_ref()
                 .map(|x| ce.score > x.score)
                 .unwrap_or(true)
             {
-                best_feasible = Some(ce.clone());
+                best_any = Some(ce.clone());
                 improved = true;
             }
-            push_topk(&mut top_feasible, ce.clone(), 14);
-        }
-
-        push_topk(&mut top_all, ce.clone(), 20);
 
-        if improved {
-            sigma = (sigma * 0.90).max(0.25);
-            stagnation = 0;
-        } else {
-            stagnation += 1;
-            if stagnation % 12 == 0 {
-                sigma = (sigma * 1.20).min(2.5);
+            if is_feasible(&ce.agg) {
+                feasible_count += 1;
+                if best_feasible
+                    .as_ref()
+                    .map(|x| ce.score > x.score)
+                    .unwrap_or(true)
+                {
+                    best_feasible = Some(ce.clone());
+                    improved = true;
+                }
+                push_topk(&mut top_feasible, ce.clone(), 14);
             }
-            if stagnation % 40 == 0 {
-                sigma = (sigma * 1.30).min(2.5);
+
+            push_topk(&mut top_all, ce.clone(), 20);
+
+            if improved {
+                sigma = (sigma * 0.90).max(0.25);
+                stagnation = 0;
+            } else {
+                stagnation += 1;
+                if stagnation % 12 == 0 {
+                    sigma = (sigma * 1.20).min(2.5);
+                }
+                if stagnation % 40 == 0 {
+                    sigma = (sigma * 1.30).min(2.5);
+                }
             }
-        }
 
-        println!(
-            "[local {}/{}] score={:.2} sum_net={:.2} worst_dd={:.2}% feasible={} best_feasible={} sigma={:.2}",
-            i,
-            local_trials,
-            ce.score,
-            agg_num(&ce.agg, "sum_net_profit"),
-            agg_num(&ce.agg, "worst_year_max_drawdown_pct"),
-            if is_feasible(&ce.agg) { 1 } else { 0 },
-            feasible_count,
-            sigma,
-        );
+            println!(
+                "[local {}/{}] score={:.2} sum_net={:.2} worst_dd={:.2}% feasible={} best_feasible={} sigma={:.2}",
+                i,
+                local_trials,
+                ce.score,
+                agg_num(&ce.agg, "sum_net_profit"),
+                agg_num(&ce.agg, "worst_year_intrabar_drawdown_pct"),
+                if is_feasible(&ce.agg) { 1 } else { 0 },
+                feasible_count,
+                sigma,
+            );
+        }
     }
 
     let best_any = best_any.expect("at least one candidate evaluated");
 
     let bounds_json = json!({
-        "numeric": ref_num.into_iter().map(|(k, (l, h))| (k.to_string(), json!([l, h]))).collect::<BTreeMap<_, _>>(),
-        "bool_probs": ref_bool.into_iter().map(|(k, p)| (k.to_string(), p)).collect::<BTreeMap<_, _>>(),
+        "numeric": ref_num.into_iter().map(|(k, (l, h))| (k, json!([l, h]))).collect::<BTreeMap<_, _>>(),
+        "bool_probs": ref_bool.into_iter().collect::<BTreeMap<_, _>>(),
         "global_trials": global_trials,
         "local_trials": local_trials,
         "evaluated": eval_count,
+        "pruned": pruned,
     });
 
-    (best_any, best_feasible, bounds_json)
-}
-
-fn add_fixed_params(p: &mut Map<String, Value>) {
-    // Amazing3.1.mq4.bak defaults (all non-optimized params are pinned here).
-    p.insert("on_top_not_buy_first".to_string(), Value::from(0.0));
-    p.insert("on_under_not_sell_first".to_string(), Value::from(0.0));
-    p.insert("on_top_not_buy_add".to_string(), Value::from(0.0));
-    p.insert("on_under_not_sell_add".to_string(), Value::from(0.0));
-
-    p.insert("limit_start_time".to_string(), Value::from("00:00"));
-    p.insert("limit_stop_time".to_string(), Value::from("24:00"));
-    p.insert("ea_start_time".to_string(), Value::from("00:00"));
-    p.insert("ea_stop_time".to_string(), Value::from("24:00"));
-
-    p.insert("close_buy_sell".to_string(), Value::from(true));
-    p.insert("homeopathy_close_all".to_string(), Value::from(true));
-    p.insert("homeopathy".to_string(), Value::from(false));
-    p.insert("over".to_string(), Value::from(false));
-    p.insert("next_time".to_string(), Value::from(0));
-    p.insert("money".to_string(), Value::from(0.0));
-
-    p.insert("first_step".to_string(), Value::from(30));
-    p.insert("min_distance".to_string(), Value::from(60));
-    p.insert("two_min_distance".to_string(), Value::from(60));
-    p.insert("step_trail_orders".to_string(), Value::from(5));
-    p.insert("two_step".to_string(), Value::from(100));
-
-    p.insert("max_loss".to_string(), Value::from(100_000.0));
-    p.insert("max_loss_close_all".to_string(), Value::from(50.0));
-    p.insert("max_lot".to_string(), Value::from(10.0));
-    p.insert("plus_lot".to_string(), Value::from(0.0));
-    p.insert("digits_lot".to_string(), Value::from(2));
-    p.insert("close_all".to_string(), Value::from(0.5));
-    p.insert("profit_by_count".to_string(), Value::from(true));
-    p.insert("stop_profit".to_string(), Value::from(2.0));
-
-    p.insert("totals".to_string(), Value::from(50));
-    p.insert("max_spread".to_string(), Value::from(32));
-    p.insert("leverage_min".to_string(), Value::from(100));
-
-    p.insert("open_mode".to_string(), Value::from(3));
-    p.insert("sleep_seconds".to_string(), Value::from(30));
-    p.insert("stop_loss".to_string(), Value::from(0.0));
-    p.insert("check_margin_for_add_orders".to_string(), Value::from(false));
+    Ok((best_any, best_feasible, top_all, bounds_json))
 }
 
-fn repair_candidate(p: &mut Map<String, Value>, specs: &[ParamSpec]) {
-    for s in specs {
+fn repair_candidate(p: &mut Map<String, Value>, space: &SearchSpace) {
+    for s in &space.params {
         match s.kind {
             ParamKind::Bool { p_true } => {
-                let v = get_bool(p, s.name, p_true >= 0.5);
-                p.insert(s.name.to_string(), Value::from(v));
+                let v = get_bool(p, &s.name, p_true >= 0.5);
+                p.insert(s.name.clone(), Value::from(v));
             }
             ParamKind::Int { low, high, step } => {
                 let default_v = quantize_i64((low + high) / 2, low, high, step);
-                let v = quantize_i64(get_i64(p, s.name, default_v), low, high, step);
-                p.insert(s.name.to_string(), Value::from(v));
+                let v = quantize_i64(get_i64(p, &s.name, default_v), low, high, step);
+                p.insert(s.name.clone(), Value::from(v));
             }
             ParamKind::Float {
                 low,
@@ -2299,40 +1342,19 @@ fn repair_candidate(p: &mut Map<String, Value>, specs: &[ParamSpec]) {
                 step,
                 precision,
             } => {
-                let v = quantize_f64(get_f64(p, s.name, low), low, high, step, precision);
-                p.insert(s.name.to_string(), Value::from(v));
+                let v = quantize_f64(get_f64(p, &s.name, low), low, high, step, precision);
+                p.insert(s.name.clone(), Value::from(v));
+            }
+            ParamKind::Categorical { ref values } => {
+                if !p.get(&s.name).is_some_and(|v| values.contains(v)) {
+                    p.insert(s.name.clone(), values[0].clone());
+                }
             }
         }
     }
 
-    let min_distance = get_i64(p, "min_distance", 60);
-    let two_min_distance = get_i64(p, "two_min_distance", min_distance);
-    if two_min_distance < min_distance {
-        p.insert("two_min_distance".to_string(), Value::from(min_distance));
-    }
-
-    let step = get_i64(p, "step", 100);
-    let two_step = get_i64(p, "two_step", step);
-    if two_step < step {
-        p.insert("two_step".to_string(), Value::from(step));
-    }
-
-    let lot = get_f64(p, "lot", 0.01);
-    let max_lot = get_f64(p, "max_lot", lot);
-    if max_lot < lot + 0.01 {
-        p.insert("max_lot".to_string(), Value::from(round_to(lot + 0.01, 2)));
-    }
-
-    let max_loss_close_all = get_f64(p, "max_loss_close_all", 50.0);
-    let max_loss = get_f64(p, "max_loss", 100_000.0);
-    if max_loss < max_loss_close_all + 5_000.0 {
-        p.insert(
-            "max_loss".to_string(),
-            Value::from(round_to(max_loss_close_all + 5_000.0, 1)),
-        );
-    }
-
-    add_fixed_params(p);
+    space.apply_fixed(p);
+    space.enforce(p);
 }
 
 fn push_topk(buf: &mut Vec<CandidateEval>, cand: CandidateEval, k: usize) {
@@ -2410,10 +1432,8 @@ fn get_bool(map: &Map<String, Value>, key: &str, default: bool) -> bool {
         .and_then(|v| {
             if let Some(b) = v.as_bool() {
                 Some(b)
-            } else if let Some(i) = v.as_i64() {
-                Some(i != 0)
             } else {
-                None
+                v.as_i64().map(|i| i != 0)
             }
         })
         .unwrap_or(default)
@@ -2448,7 +1468,30 @@ fn ts_to_utc(ts: i64) -> String {
 }
 
 #[derive(Parser, Debug)]
-#[command(author, version, about = "AUDNZD parameter optimizer in pure Rust")]
+#[command(
+    author,
+    version,
+    about = "AUDNZD parameter optimizer in pure Rust",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Build a merged M5 (or tick) file from Dukascopy .bi5 ticks and CSV exports.
+    Import(ImportArgs),
+    /// Stitch the yearly `{symbol}-m5-bid-*.csv` fragments into one merged file.
+    Merge(MergeArgs),
+    /// Check a bar file for gaps, duplicates, bad OHLC and spikes.
+    Audit(AuditArgs),
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
     #[arg(long, default_value = "AUDNZD")]
     symbol: String,
@@ -2458,21 +1501,230 @@ struct Args {
     trials: usize,
     #[arg(long, default_value_t = 20260226)]
     seed: u64,
+    /// TOML or JSON file declaring which parameters to search and over what ranges; the
+    /// built-in `step`/`lot`/`k_lot` space when omitted.
+    #[arg(long)]
+    search_space: Option<PathBuf>,
+    /// Walk-forward mode: optimize on this many yearly windows at a time and run each winner
+    /// on the windows that follow, instead of one optimization over all years.
+    #[arg(long)]
+    walk_forward: Option<usize>,
+    /// Out-of-sample yearly windows after each walk-forward in-sample window.
+    #[arg(long, default_value_t = 1)]
+    walk_forward_oos_years: usize,
+    /// Start every in-sample window at the first year instead of rolling it forward.
+    #[arg(long)]
+    walk_forward_anchored: bool,
+    /// Keep the last this many of the `--years` windows out of the search and report how the
+    /// best candidates do on them.
+    #[arg(long, conflicts_with = "walk_forward")]
+    holdout_years: Option<usize>,
+    /// Candidates, by in-sample score, run on the hold-out besides the best ones.
+    #[arg(long, default_value_t = 5)]
+    holdout_top_k: usize,
+    /// Pick `selected_result` by hold-out score instead of in-sample score.
+    #[arg(long, requires = "holdout_years")]
+    select_by_holdout: bool,
+    /// Worker threads for evaluating candidates and, within a candidate, its years; 0 uses
+    /// every core. Results for a seed are the same on any thread count.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
     #[arg(long, default_value_t = 80.0)]
     drawdown_limit: f64,
     #[arg(long, default_value = "optimized_params_audnzd_10y_dd80_rust.json")]
     out: PathBuf,
     #[arg(long)]
     data_file: Option<PathBuf>,
+    /// Ask-side OHLC CSV paired with the bid data; the close-to-close difference becomes the
+    /// bar's spread. Defaults to the `-ask-` sibling of a `-bid-` data file when one exists.
+    #[arg(long)]
+    ask_file: Option<PathBuf>,
+    /// Tick CSV (timestamp,bid,ask[,bid_volume,ask_volume]); runs the strategy on every tick
+    /// with recorded quotes instead of synthetic bar prices.
+    #[arg(long, conflicts_with = "data_file")]
+    tick_file: Option<PathBuf>,
+    /// Parse the CSV every run instead of using its `.barcache` file.
+    #[arg(long)]
+    no_bar_cache: bool,
+    /// What to do with bars the data audit flags before simulation.
+    #[arg(long, value_enum, default_value_t = AuditAction::Report)]
+    audit: AuditAction,
+    /// Spike threshold of the data audit, in multiples of the median bar range.
+    #[arg(long, default_value_t = 30.0)]
+    outlier_factor: f64,
+    #[arg(long, value_enum, default_value_t = PricePath::Close)]
+    price_path: PricePath,
+    #[arg(long, default_value_t = 12)]
+    ticks_per_bar: usize,
+    #[arg(long, value_enum, default_value_t = SpreadModelKind::Recorded)]
+    spread_model: SpreadModelKind,
+    /// Spread for --spread-model fixed, in pips.
+    #[arg(long, default_value_t = 1.0)]
+    spread_pips: f64,
+    #[arg(long, value_enum, default_value_t = SlippageModelKind::Volatility)]
+    slippage_model: SlippageModelKind,
+    /// Slippage for --slippage-model fixed, in pips.
+    #[arg(long, default_value_t = 0.5)]
+    slippage_pips: f64,
+    /// JSON table of symbol specifications keyed by symbol name.
+    #[arg(long, default_value = "symbols.json")]
+    symbols_file: PathBuf,
+    /// Swap per lot per night for long positions (negative = charged); overrides the spec.
+    #[arg(long, allow_negative_numbers = true)]
+    swap_long: Option<f64>,
+    /// Swap per lot per night for short positions (negative = charged); overrides the spec.
+    #[arg(long, allow_negative_numbers = true)]
+    swap_short: Option<f64>,
+    #[arg(long, value_enum)]
+    swap_mode: Option<SwapMode>,
+    /// Minimum distance in points between market and pending price; overrides the spec.
+    #[arg(long)]
+    stop_level: Option<i32>,
+    /// Distance in points within which pending orders are frozen; overrides the spec.
+    #[arg(long)]
+    freeze_level: Option<i32>,
+    #[arg(long, default_value_t = 0)]
+    rollover_hour: u32,
+    /// Broker server clock offset from UTC in hours outside DST (e.g. 2 for GMT+2/+3 brokers).
+    /// CSV and tick data are UTC and get moved onto this clock; MT4 history files already are.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    server_utc_offset: f64,
+    #[arg(long, value_enum, default_value_t = DstRule::None)]
+    server_dst: DstRule,
+    /// Commission charged on each side of a trade, in --commission-mode units.
+    #[arg(long, default_value_t = 0.0)]
+    commission: f64,
+    #[arg(long, value_enum, default_value_t = CommissionMode::PerLot)]
+    commission_mode: CommissionMode,
+    /// Margin-call level in --stop-out-mode units; 0 disables.
+    #[arg(long, default_value_t = 100.0)]
+    margin_call_level: f64,
+    /// Stop-out level in --stop-out-mode units; 0 disables (any free-margin shortfall is a blow-up).
+    #[arg(long, default_value_t = 50.0)]
+    stop_out_level: f64,
+    #[arg(long, value_enum, default_value_t = StopOutMode::Percent)]
+    stop_out_mode: StopOutMode,
+    /// Deposit currency; defaults to USD when a rate for it is found (--conversion-rate,
+    /// the symbol's tick_value or a QUOTE/USD series in download/), else to the symbol's base
+    /// currency.
+    #[arg(long)]
+    account_currency: Option<String>,
+    /// Fixed account-currency units per unit of the symbol's quote currency.
+    #[arg(long)]
+    conversion_rate: Option<f64>,
+    /// Symbol pairing the quote and account currencies (e.g. NZDUSD), loaded from download/.
+    #[arg(long)]
+    conversion_symbol: Option<String>,
+    /// CSV for --conversion-symbol, skipping the download/ lookup.
+    #[arg(long)]
+    conversion_file: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let cli = Cli::parse();
+    match &cli.command {
+        Some(Command::Import(a)) => run_import(a),
+        Some(Command::Merge(a)) => run_merge(a),
+        Some(Command::Audit(a)) => run_audit(a),
+        None => run_optimize(cli.args),
+    }
+}
+
+fn run_optimize(args: Args) -> Result<()> {
     if args.trials == 0 {
         bail!("--trials must be >= 1");
     }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .context("failed to start worker threads")?;
+    let space = match &args.search_space {
+        Some(path) => SearchSpace::load(path)?,
+        None => SearchSpace::builtin(),
+    };
+
+    let mut spec = match SymbolSpec::load(&args.symbols_file, &args.symbol)? {
+        Some(spec) => {
+            println!("spec={} from {}", args.symbol, args.symbols_file.display());
+            spec
+        }
+        None => {
+            println!(
+                "spec={} not in {}, using defaults",
+                args.symbol,
+                args.symbols_file.display()
+            );
+            SymbolSpec::fallback(&args.symbol)
+        }
+    };
+    if let Some(v) = args.swap_long {
+        spec.swap_long = v;
+    }
+    if let Some(v) = args.swap_short {
+        spec.swap_short = v;
+    }
+    if let Some(v) = args.swap_mode {
+        spec.swap_mode = v;
+    }
+    if let Some(v) = args.stop_level {
+        spec.stop_level = v;
+    }
+    if let Some(v) = args.freeze_level {
+        spec.freeze_level = v;
+    }
 
-    let (data_file, bars) = load_or_select_data(&args.symbol, args.years, &args.data_file)?;
+    let mut audit_report = None;
+    let (data_file, mut bars, mut ticks) = match &args.tick_file {
+        Some(path) => {
+            let (ticks, bars, info) = load_tick_data(path, args.years)?;
+            println!("ticks={}", ticks.len());
+            if let Some(info) = &info {
+                apply_history_info(&mut spec, info, &args.symbol);
+            }
+            (path.clone(), bars.into(), ticks)
+        }
+        None => {
+            let (data_file, bars, skipped, info) = load_or_select_data(
+                &args.symbol,
+                args.years,
+                &args.data_file,
+                !args.no_bar_cache,
+            )?;
+            if let Some(info) = &info {
+                apply_history_info(&mut spec, info, &args.symbol);
+            }
+            let opts = AuditOptions {
+                action: args.audit,
+                outlier_factor: args.outlier_factor,
+            };
+            let (mut bars, report) = audit_bars(bars, skipped, &opts)?;
+            report.print_summary();
+            audit_report = Some(report);
+            if let Some(ask_file) = args
+                .ask_file
+                .clone()
+                .or_else(|| paired_ask_file(&data_file))
+            {
+                let ask_bars = load_bars_from_csv(&ask_file)?;
+                let matched = apply_ask_spread(bars.to_mut(), &ask_bars, spec.point);
+                println!(
+                    "ask={} matched_bars={}/{}",
+                    ask_file.display(),
+                    matched,
+                    bars.len()
+                );
+            }
+            (data_file, bars, Vec::new())
+        }
+    };
+    let server_time = ServerTime {
+        utc_offset_hours: args.server_utc_offset,
+        dst: args.server_dst,
+    };
+    if !server_time.is_utc() && !is_mt4_history(&data_file) {
+        server_time.shift_bars(bars.to_mut());
+        server_time.shift_ticks(&mut ticks);
+    }
     let yearly_bars = split_into_year_windows(&bars, args.years);
     if yearly_bars.len() < args.years {
         bail!(
@@ -2485,28 +1737,92 @@ fn main() -> Result<()> {
 
     println!("data={}", data_file.display());
     println!(
-        "symbol={} bars={} years={} trials={} drawdown_limit={:.2}%",
+        "symbol={} bars={} years={} trials={} drawdown_limit={:.2}% threads={}",
         args.symbol,
         bars.len(),
         yearly_bars.len(),
         args.trials,
-        args.drawdown_limit
+        args.drawdown_limit,
+        rayon::current_num_threads()
     );
 
-    let specs = param_specs();
-    let (best_any, best_feasible, bounds_json) = optimize_params(
-        &specs,
+    let fixed_rate = args.conversion_rate.or_else(|| spec.tick_value_rate());
+    let end_d = Utc::now().date_naive();
+    let start_d = end_d - Duration::days(365 * args.years as i64);
+    // Without a configured rate, a QUOTE/ACCOUNT (or ACCOUNT/QUOTE) series in download/ is
+    // used when there is one.
+    let conversion_file = match (&args.conversion_symbol, fixed_rate) {
+        (Some(conv_symbol), _) => {
+            let path = match &args.conversion_file {
+                Some(p) => p.clone(),
+                None => select_merged_file(conv_symbol, start_d, end_d)?,
+            };
+            Some((conv_symbol.clone(), path))
+        }
+        (None, Some(_)) => None,
+        (None, None) => {
+            QuoteConversion::series_symbols(&args.symbol, args.account_currency.as_deref())
+                .into_iter()
+                .find_map(|s| {
+                    let path = select_merged_file(&s, start_d, end_d).ok()?;
+                    Some((s, path))
+                })
+        }
+    };
+    let conversion_series = match &conversion_file {
+        Some((conv_symbol, path)) => {
+            let rates: Vec<(i64, f64)> = load_bars_from_csv(path)?
+                .iter()
+                .map(|b| (server_time.to_server(b.ts), b.close))
+                .collect();
+            println!("conversion={} data={}", conv_symbol, path.display());
+            Some((conv_symbol.as_str(), rates))
+        }
+        None => None,
+    };
+    let (account_currency, quote_conversion) = QuoteConversion::resolve(
         &args.symbol,
-        &yearly_bars,
-        args.drawdown_limit,
-        args.trials,
-        args.seed,
-    );
+        args.account_currency.as_deref(),
+        fixed_rate,
+        conversion_series,
+    )?;
+    let rate_source = match (&quote_conversion, args.conversion_rate) {
+        (QuoteConversion::Fixed { .. }, None) => " from symbol tick_value",
+        _ => "",
+    };
+    println!("account_currency={account_currency} conversion={quote_conversion}{rate_source}");
+    if args.account_currency.is_none() && account_currency != DEFAULT_ACCOUNT {
+        println!(
+            "note: no rate to {DEFAULT_ACCOUNT} found; the account is in the base currency. \
+             Pass --account-currency with --conversion-rate or --conversion-symbol to change it"
+        );
+    }
 
-    let chosen = best_feasible.clone().unwrap_or_else(|| best_any.clone());
-    let feasible_found = best_feasible.is_some();
+    let sim = SimConfig {
+        price_path: if ticks.is_empty() {
+            args.price_path
+        } else {
+            PricePath::Ticks
+        },
+        ticks: Arc::new(ticks),
+        ticks_per_bar: args.ticks_per_bar,
+        spec,
+        spread_model: args.spread_model,
+        spread_pips: args.spread_pips,
+        slippage_model: args.slippage_model,
+        slippage_pips: args.slippage_pips,
+        rollover_hour: args.rollover_hour,
+        server_time,
+        commission: args.commission,
+        commission_mode: args.commission_mode,
+        margin_call_level: args.margin_call_level,
+        stop_out_level: args.stop_out_level,
+        stop_out_mode: args.stop_out_mode,
+        account_currency,
+        quote_conversion,
+    };
 
-    let payload = json!({
+    let mut payload = json!({
         "objective": format!("maximize profit on {} with worst drawdown < {:.2}%", args.symbol, args.drawdown_limit),
         "symbol": args.symbol,
         "drawdown_limit_pct": args.drawdown_limit,
@@ -2516,26 +1832,110 @@ fn main() -> Result<()> {
         "trials": args.trials,
         "seed": args.seed,
         "algorithm": "adaptive elite search + boundary refinement",
-        "chosen_boundaries": bounds_json,
-        "feasible_found": feasible_found,
-        "best_feasible": best_feasible.as_ref().map(|x| json!({
-            "score": x.score,
-            "params": x.params,
-            "aggregate": x.agg,
-            "yearly_results": x.years,
-        })),
-        "best_any": {
-            "score": best_any.score,
-            "params": best_any.params,
-            "aggregate": best_any.agg,
-            "yearly_results": best_any.years,
-        },
-        "selected_result": {
-            "score": chosen.score,
-            "params": chosen.params,
-            "aggregate": chosen.agg,
-            "yearly_results": chosen.years,
+        "simulation": sim,
+        "data_audit": audit_report,
+        "search_space": space,
+    });
+
+    if let Some(in_sample_years) = args.walk_forward {
+        let plan = WalkForward {
+            in_sample_years,
+            out_of_sample_years: args.walk_forward_oos_years,
+            anchored: args.walk_forward_anchored,
+        };
+        let report = walk_forward(
+            plan,
+            &space,
+            &args.symbol,
+            &yearly_bars,
+            &sim,
+            args.drawdown_limit,
+            args.trials,
+            args.seed,
+        )?;
+        println!(
+            "\nWalk-forward folds={} oos_net={:.2} efficiency={}",
+            report.folds(),
+            report.oos_net_profit(),
+            report
+                .efficiency()
+                .map_or("-".to_string(), |e| format!("{e:.3}"))
+        );
+        payload["walk_forward"] = serde_json::to_value(&report)?;
+        fs::write(&args.out, serde_json::to_string_pretty(&payload)?)
+            .with_context(|| format!("failed to write {}", args.out.display()))?;
+        println!("Saved result: {}", args.out.display());
+        return Ok(());
+    }
+
+    let holdout_years = args.holdout_years.unwrap_or(0);
+    if holdout_years >= yearly_bars.len() {
+        bail!(
+            "--holdout-years must leave at least one of the {} years to optimize on",
+            yearly_bars.len()
+        );
+    }
+    let (search_bars, holdout_bars) = yearly_bars.split_at(yearly_bars.len() - holdout_years);
+
+    let (best_any, best_feasible, top, bounds_json) = optimize_params(
+        &space,
+        &args.symbol,
+        search_bars,
+        &sim,
+        args.drawdown_limit,
+        args.trials,
+        args.seed,
+    )?;
+
+    let mut chosen = best_feasible.clone().unwrap_or_else(|| best_any.clone());
+    let feasible_found = best_feasible.is_some();
+
+    if !holdout_bars.is_empty() {
+        let holdout = Holdout {
+            bars: holdout_bars,
+            first_year: search_bars.len(),
+        };
+        let top = &top[..args.holdout_top_k.clamp(1, top.len())];
+        let mut oos = evaluate_holdout(
+            &best_any,
+            best_feasible.as_ref(),
+            top,
+            &holdout,
+            &args.symbol,
+            &sim,
+            args.drawdown_limit,
+        );
+        if args.select_by_holdout {
+            chosen = top[oos.select_by_holdout()].clone();
         }
+        println!(
+            "\nHold-out years {}-{}: selected sum_net={:.2}",
+            search_bars.len() + 1,
+            yearly_bars.len(),
+            oos.selected_net_profit()
+        );
+        payload["out_of_sample"] = serde_json::to_value(&oos)?;
+    }
+
+    payload["chosen_boundaries"] = bounds_json;
+    payload["feasible_found"] = json!(feasible_found);
+    payload["best_feasible"] = json!(best_feasible.as_ref().map(|x| json!({
+        "score": x.score,
+        "params": x.params,
+        "aggregate": x.agg,
+        "yearly_results": x.years,
+    })));
+    payload["best_any"] = json!({
+        "score": best_any.score,
+        "params": best_any.params,
+        "aggregate": best_any.agg,
+        "yearly_results": best_any.years,
+    });
+    payload["selected_result"] = json!({
+        "score": chosen.score,
+        "params": chosen.params,
+        "aggregate": chosen.agg,
+        "yearly_results": chosen.years,
     });
 
     fs::write(&args.out, serde_json::to_string_pretty(&payload)?)
@@ -2546,7 +1946,7 @@ fn main() -> Result<()> {
         "Selected score={:.2} sum_net={:.2} worst_dd={:.2}% feasible={}",
         chosen.score,
         agg_num(&chosen.agg, "sum_net_profit"),
-        agg_num(&chosen.agg, "worst_year_max_drawdown_pct"),
+        agg_num(&chosen.agg, "worst_year_intrabar_drawdown_pct"),
         if is_feasible(&chosen.agg) { 1 } else { 0 }
     );
 
@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Datelike, Weekday};
+
+use crate::import::{merged_path, write_bars};
+use crate::sim_broker::Bar;
+use crate::symbol_spec::SymbolSpec;
+use crate::{load_bars_from_csv, ts_to_utc};
+
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+    #[arg(long, default_value = "AUDNZD")]
+    pub symbol: String,
+    /// Directory holding the `{symbol}-m5-bid-*.csv` fragments; the merged file goes here too.
+    #[arg(long, default_value = "download")]
+    pub dir: PathBuf,
+    /// Report holes between bars longer than this many minutes.
+    #[arg(long, default_value_t = 60)]
+    pub gap_minutes: i64,
+    #[arg(long, default_value = "symbols.json")]
+    pub symbols_file: PathBuf,
+}
+
+pub fn run_merge(args: &MergeArgs) -> Result<()> {
+    let spec = SymbolSpec::load(&args.symbols_file, &args.symbol)?
+        .unwrap_or_else(|| SymbolSpec::fallback(&args.symbol));
+
+    let prefix = format!("{}-m5-bid-", args.symbol.to_lowercase());
+    let mut fragments: Vec<PathBuf> = fs::read_dir(&args.dir)
+        .with_context(|| format!("failed to read {}", args.dir.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name().and_then(|x| x.to_str()).is_some_and(|n| {
+                n.starts_with(&prefix) && n.ends_with(".csv") && !n.ends_with("-merged.csv")
+            })
+        })
+        .collect();
+    fragments.sort();
+    if fragments.is_empty() {
+        bail!("no {prefix}*.csv fragments in {}", args.dir.display());
+    }
+
+    // Fragments are taken in name order and the first copy of a timestamp wins.
+    let mut bars: Vec<Bar> = Vec::new();
+    for path in &fragments {
+        let part = load_bars_from_csv(path)?;
+        println!(
+            "fragment={} bars={} {} -> {}",
+            path.display(),
+            part.len(),
+            part.first().map(|b| ts_to_utc(b.ts)).unwrap_or_default(),
+            part.last().map(|b| ts_to_utc(b.ts)).unwrap_or_default()
+        );
+        bars.extend(part);
+    }
+    let total = bars.len();
+    bars.sort_by_key(|b| b.ts);
+
+    let mut merged: Vec<Bar> = Vec::with_capacity(total);
+    let mut conflicts = 0_usize;
+    for b in bars {
+        match merged.last() {
+            Some(prev) if prev.ts == b.ts => {
+                if (prev.open, prev.high, prev.low, prev.close) != (b.open, b.high, b.low, b.close)
+                {
+                    conflicts += 1;
+                }
+            }
+            _ => merged.push(b),
+        }
+    }
+    if merged.is_empty() {
+        bail!("fragments in {} hold no bars", args.dir.display());
+    }
+    println!(
+        "bars={} duplicates_dropped={} conflicting_duplicates={}",
+        merged.len(),
+        total - merged.len(),
+        conflicts
+    );
+
+    report_gaps(&merged, args.gap_minutes * 60);
+
+    let path = merged_path(
+        &args.dir,
+        &args.symbol,
+        "m5-bid",
+        merged[0].ts,
+        merged[merged.len() - 1].ts,
+    )?;
+    write_bars(&path, &merged, spec.digits)?;
+    println!("Saved merged file: {}", path.display());
+    Ok(())
+}
+
+/// Prints holes longer than `min_gap` seconds. Holes spanning a Saturday are the weekend
+/// close and only counted.
+fn report_gaps(bars: &[Bar], min_gap: i64) {
+    let mut weekend = 0_usize;
+    let mut other = Vec::new();
+    for w in bars.windows(2) {
+        let gap = w[1].ts - w[0].ts;
+        if gap <= min_gap {
+            continue;
+        }
+        if spans_saturday(w[0].ts, w[1].ts) {
+            weekend += 1;
+        } else {
+            other.push((w[0].ts, w[1].ts));
+        }
+    }
+
+    println!("gaps: weekend={} other={}", weekend, other.len());
+    other.sort_by_key(|(a, b)| std::cmp::Reverse(b - a));
+    for (from, to) in other.iter().take(20) {
+        println!(
+            "  gap {} -> {} ({:.1}h)",
+            ts_to_utc(*from),
+            ts_to_utc(*to),
+            (to - from) as f64 / 3600.0
+        );
+    }
+}
+
+fn spans_saturday(from: i64, to: i64) -> bool {
+    let day = 86_400;
+    let mut d = from.div_euclid(day);
+    while d * day <= to {
+        if DateTime::from_timestamp(d * day, 0).is_some_and(|dt| dt.weekday() == Weekday::Sat) {
+            return true;
+        }
+        d += 1;
+    }
+    false
+}
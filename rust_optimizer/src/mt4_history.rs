@@ -0,0 +1,278 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+use crate::import::to_m5;
+use crate::sim_broker::Bar;
+use crate::tick_data::{Tick, ticks_to_bars};
+
+const HST_HEADER: usize = 148;
+const HST_V400_RECORD: usize = 44;
+const HST_V401_RECORD: usize = 60;
+const FXT_HEADER: usize = 728;
+const FXT_RECORD: usize = 56;
+
+/// What an MT4 history file says about itself.
+#[derive(Clone, Debug)]
+pub struct HistoryInfo {
+    pub symbol: String,
+    /// Bar period in minutes.
+    pub period: i32,
+    pub digits: i32,
+    /// Fixed spread in points from an `.fxt` header; `.hst` files keep it per bar.
+    pub spread: Option<i32>,
+}
+
+/// True for the files `read_history_bars` understands.
+pub fn is_mt4_history(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("hst") || e.eq_ignore_ascii_case("fxt"))
+}
+
+/// Reads an `.hst` or `.fxt` file as M5 bars. M1 history is folded into M5; longer `.hst`
+/// periods cannot drive the M5 simulation and are refused. `.fxt` ticks are built into M5
+/// bars whatever chart period they were generated for.
+pub fn read_history_bars(path: &Path) -> Result<(HistoryInfo, Vec<Bar>)> {
+    let is_fxt = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("fxt"));
+    let (info, bars) = if is_fxt {
+        let (info, ticks) = read_fxt(path)?;
+        let mut bars = ticks_to_bars(&ticks);
+        for b in &mut bars {
+            b.spread = info.spread.map_or(f64::NAN, |s| s as f64);
+        }
+        (info, bars)
+    } else {
+        read_hst(path)?
+    };
+    if !is_fxt && (info.period > 5 || 5 % info.period.max(1) != 0) {
+        bail!(
+            "{} holds M{} bars; the simulation needs M1 or M5 history",
+            path.display(),
+            info.period
+        );
+    }
+    Ok((info, to_m5(bars)))
+}
+
+/// Reads an MT4 `.hst` bar file, format 400 (`time` as i32, prices open/low/high/close) or
+/// 401 (`time` as i64, prices open/high/low/close, then volumes and the spread in points).
+pub fn read_hst(path: &Path) -> Result<(HistoryInfo, Vec<Bar>)> {
+    let raw = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if raw.len() < HST_HEADER {
+        bail!("{} is too short for an hst header", path.display());
+    }
+    let version = i32_at(&raw, 0);
+    let info = HistoryInfo {
+        symbol: c_string(&raw[68..80]),
+        period: i32_at(&raw, 80),
+        digits: i32_at(&raw, 84),
+        spread: None,
+    };
+
+    let body = &raw[HST_HEADER..];
+    let bars: Vec<Bar> = match version {
+        400 => body
+            .chunks_exact(HST_V400_RECORD)
+            .map(|r| Bar {
+                ts: i32_at(r, 0) as i64,
+                open: f64_at(r, 4),
+                low: f64_at(r, 12),
+                high: f64_at(r, 20),
+                close: f64_at(r, 28),
+                spread: f64::NAN,
+            })
+            .collect(),
+        401 => body
+            .chunks_exact(HST_V401_RECORD)
+            .map(|r| {
+                let spread = i32_at(r, 48);
+                Bar {
+                    ts: i64_at(r, 0),
+                    open: f64_at(r, 8),
+                    high: f64_at(r, 16),
+                    low: f64_at(r, 24),
+                    close: f64_at(r, 32),
+                    spread: if spread > 0 { spread as f64 } else { f64::NAN },
+                }
+            })
+            .collect(),
+        v => bail!("{}: unsupported hst version {v}", path.display()),
+    };
+    Ok((info, bars))
+}
+
+/// Reads an MT4 strategy tester `.fxt` file (format 405). Records carry only the bid, so
+/// the ask is the bid plus the fixed spread from the header.
+pub fn read_fxt(path: &Path) -> Result<(HistoryInfo, Vec<Tick>)> {
+    let raw = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if raw.len() < FXT_HEADER {
+        bail!("{} is too short for an fxt header", path.display());
+    }
+    let version = i32_at(&raw, 0);
+    if version != 405 {
+        bail!("{}: unsupported fxt version {version}", path.display());
+    }
+    let points = i32_at(&raw, 252).max(0);
+    let info = HistoryInfo {
+        symbol: c_string(&raw[196..208]),
+        period: i32_at(&raw, 208),
+        digits: i32_at(&raw, 256),
+        spread: Some(points),
+    };
+    let spread = points as f64 * f64_at(&raw, 264);
+
+    let mut ticks = raw[FXT_HEADER..]
+        .chunks_exact(FXT_RECORD)
+        .map(|r| {
+            let bid = f64_at(r, 32);
+            Tick {
+                ts_ms: i32_at(r, 48) as i64 * 1000,
+                bid,
+                ask: bid + spread,
+            }
+        })
+        .filter(|t| t.bid > 0.0)
+        .collect::<Vec<_>>();
+    ticks.sort_by_key(|t| t.ts_ms);
+    Ok((info, ticks))
+}
+
+fn i32_at(b: &[u8], at: usize) -> i32 {
+    i32::from_le_bytes(b[at..at + 4].try_into().expect("4 bytes"))
+}
+
+fn i64_at(b: &[u8], at: usize) -> i64 {
+    i64::from_le_bytes(b[at..at + 8].try_into().expect("8 bytes"))
+}
+
+fn f64_at(b: &[u8], at: usize) -> f64 {
+    f64::from_le_bytes(b[at..at + 8].try_into().expect("8 bytes"))
+}
+
+fn c_string(b: &[u8]) -> String {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    String::from_utf8_lossy(&b[..end]).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    /// Writes `bytes` to a temp file ending in `suffix`, removed when dropped.
+    fn temp_file(suffix: &str, bytes: &[u8]) -> NamedTempFile {
+        let file = tempfile::Builder::new()
+            .suffix(suffix)
+            .tempfile()
+            .expect("create temp file");
+        fs::write(file.path(), bytes).expect("write temp file");
+        file
+    }
+
+    fn put(buf: &mut [u8], at: usize, bytes: &[u8]) {
+        buf[at..at + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn hst_header(version: i32, period: i32) -> Vec<u8> {
+        let mut h = vec![0_u8; HST_HEADER];
+        put(&mut h, 0, &version.to_le_bytes());
+        put(&mut h, 68, b"AUDNZD");
+        put(&mut h, 80, &period.to_le_bytes());
+        put(&mut h, 84, &5_i32.to_le_bytes());
+        h
+    }
+
+    #[test]
+    fn reads_hst_v400() {
+        let mut file = hst_header(400, 5);
+        let mut r = [0_u8; HST_V400_RECORD];
+        put(&mut r, 0, &1_167_609_600_i32.to_le_bytes());
+        // open, low, high, close, volume
+        for (i, v) in [1.1, 1.0, 1.3, 1.2, 42.0].iter().enumerate() {
+            put(&mut r, 4 + 8 * i, &f64::to_le_bytes(*v));
+        }
+        file.extend_from_slice(&r);
+        let tmp = temp_file(".hst", &file);
+        let (info, bars) = read_hst(tmp.path()).unwrap();
+
+        assert_eq!(
+            (info.symbol.as_str(), info.period, info.digits),
+            ("AUDNZD", 5, 5)
+        );
+        assert_eq!(bars.len(), 1);
+        let b = &bars[0];
+        assert_eq!(b.ts, 1_167_609_600);
+        assert_eq!((b.open, b.high, b.low, b.close), (1.1, 1.3, 1.0, 1.2));
+        assert_eq!(b.spread(), None);
+    }
+
+    #[test]
+    fn reads_hst_v401() {
+        let mut file = hst_header(401, 1);
+        let mut r = [0_u8; HST_V401_RECORD];
+        put(&mut r, 0, &1_167_609_660_i64.to_le_bytes());
+        // open, high, low, close
+        for (i, v) in [1.1, 1.3, 1.0, 1.2].iter().enumerate() {
+            put(&mut r, 8 + 8 * i, &f64::to_le_bytes(*v));
+        }
+        put(&mut r, 40, &7_i64.to_le_bytes());
+        put(&mut r, 48, &18_i32.to_le_bytes());
+        file.extend_from_slice(&r);
+        let tmp = temp_file(".hst", &file);
+        let (info, bars) = read_hst(tmp.path()).unwrap();
+
+        assert_eq!(info.period, 1);
+        assert_eq!(bars.len(), 1);
+        let b = &bars[0];
+        assert_eq!(b.ts, 1_167_609_660);
+        assert_eq!((b.open, b.high, b.low, b.close), (1.1, 1.3, 1.0, 1.2));
+        assert_eq!(b.spread(), Some(18.0));
+    }
+
+    #[test]
+    fn refuses_unknown_hst_version() {
+        let tmp = temp_file(".hst", &hst_header(500, 5));
+        assert!(read_hst(tmp.path()).is_err());
+    }
+
+    #[test]
+    fn reads_fxt_405() {
+        let mut file = vec![0_u8; FXT_HEADER];
+        put(&mut file, 0, &405_i32.to_le_bytes());
+        put(&mut file, 196, b"AUDNZD");
+        put(&mut file, 208, &5_i32.to_le_bytes());
+        put(&mut file, 252, &20_i32.to_le_bytes());
+        put(&mut file, 256, &5_i32.to_le_bytes());
+        put(&mut file, 264, &0.00001_f64.to_le_bytes());
+        // Two ticks out of order; only the bid (at 32) and the time (at 48) are read.
+        for (ts, bid) in [(1_167_609_905_i32, 1.10010), (1_167_609_600, 1.10000)] {
+            let mut r = [0_u8; FXT_RECORD];
+            put(&mut r, 32, &f64::to_le_bytes(bid));
+            put(&mut r, 48, &ts.to_le_bytes());
+            file.extend_from_slice(&r);
+        }
+        let tmp = temp_file(".fxt", &file);
+        let (info, ticks) = read_fxt(tmp.path()).unwrap();
+        let bars = read_history_bars(tmp.path()).map(|(_, bars)| bars);
+
+        assert_eq!(
+            (info.symbol.as_str(), info.period, info.digits),
+            ("AUDNZD", 5, 5)
+        );
+        assert_eq!(info.spread, Some(20));
+        assert_eq!(ticks.len(), 2);
+        assert_eq!(ticks[0].ts_ms, 1_167_609_600_000);
+        assert_eq!(ticks[0].bid, 1.10000);
+        assert!((ticks[0].ask - 1.10020).abs() < 1e-12);
+        assert_eq!(ticks[1].ts(), 1_167_609_905);
+
+        let bars = bars.unwrap();
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].spread(), Some(20.0));
+    }
+}
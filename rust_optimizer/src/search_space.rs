@@ -0,0 +1,597 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::constraints::Relation;
+
+/// How one searched parameter is drawn.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParamKind {
+    Int {
+        low: i64,
+        high: i64,
+        step: i64,
+    },
+    Float {
+        low: f64,
+        high: f64,
+        step: f64,
+        precision: u32,
+    },
+    Bool {
+        p_true: f64,
+    },
+    Categorical {
+        values: Vec<Value>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ParamSpec {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ParamKind,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub activation: Option<Activation>,
+}
+
+/// A parameter that only matters while `active_when` holds; otherwise it is pinned to
+/// `inactive_value`, so candidates differing only in it are the same candidate.
+#[derive(Clone, Debug, Serialize)]
+pub struct Activation {
+    pub active_when: Relation,
+    pub inactive_value: Value,
+}
+
+/// The parameters the optimizer searches, the values every other strategy input is pinned
+/// to, constraints between inputs, and candidates to evaluate before any random ones.
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchSpace {
+    pub params: Vec<ParamSpec>,
+    pub fixed: Map<String, Value>,
+    pub constraints: Vec<Relation>,
+    pub seeds: Vec<Map<String, Value>>,
+}
+
+impl SearchSpace {
+    /// `step`, `lot` and `k_lot` searched; everything else at the `Amazing3.1.mq4.bak`
+    /// defaults.
+    pub fn builtin() -> Self {
+        let params = vec![
+            ParamSpec {
+                name: "step".to_string(),
+                kind: ParamKind::Int {
+                    low: 70,
+                    high: 340,
+                    step: 5,
+                },
+                activation: None,
+            },
+            ParamSpec {
+                name: "lot".to_string(),
+                kind: ParamKind::Float {
+                    low: 0.005,
+                    high: 0.10,
+                    step: 0.001,
+                    precision: 3,
+                },
+                activation: None,
+            },
+            ParamSpec {
+                name: "k_lot".to_string(),
+                kind: ParamKind::Float {
+                    low: 1.05,
+                    high: 1.45,
+                    step: 0.001,
+                    precision: 3,
+                },
+                activation: None,
+            },
+        ];
+
+        let seed = |step: i64, lot: f64, k_lot: f64| {
+            let mut s = Map::new();
+            s.insert("step".to_string(), Value::from(step));
+            s.insert("lot".to_string(), Value::from(lot));
+            s.insert("k_lot".to_string(), Value::from(k_lot));
+            s
+        };
+        let seeds = vec![
+            seed(100, 0.01, 1.3),
+            seed(130, 0.02, 1.18),
+            seed(80, 0.008, 1.35),
+            seed(180, 0.015, 1.10),
+        ];
+
+        let mut fixed = mq4_defaults();
+        for p in &params {
+            fixed.remove(&p.name);
+        }
+        Self {
+            params,
+            fixed,
+            constraints: builtin_constraints(),
+            seeds,
+        }
+    }
+
+    /// Reads a `.toml` or `.json` search space. Each entry of `params` is either a plain value
+    /// (fixed) or a table with a `type` of `fixed`, `int`, `float`, `bool` or `categorical`,
+    /// optionally with an `active_when` condition. Inputs the file does not mention stay at
+    /// the MQ4 defaults. `constraints` replaces the built-in coupling rules when present.
+    /// Optional `seeds` list candidates to try first. Parameters are searched in name order.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read search space: {}", path.display()))?;
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let root: Value = if ext.eq_ignore_ascii_case("toml") {
+            serde_json::to_value(toml::from_str::<toml::Value>(&text)?)?
+        } else if ext.eq_ignore_ascii_case("json") {
+            serde_json::from_str(&text)?
+        } else {
+            bail!(
+                "search space must be a .toml or .json file: {}",
+                path.display()
+            );
+        };
+        Self::from_value(&root).with_context(|| format!("invalid search space: {}", path.display()))
+    }
+
+    fn from_value(root: &Value) -> Result<Self> {
+        let defaults = mq4_defaults();
+        let Some(entries) = root.get("params").and_then(Value::as_object) else {
+            bail!("missing [params] table");
+        };
+
+        let mut fixed = defaults.clone();
+        let mut params = Vec::new();
+        for (name, def) in entries {
+            let Some(default) = defaults.get(name) else {
+                bail!(
+                    "unknown parameter {name}; known: {}",
+                    known_names(&defaults)
+                );
+            };
+            let Some(table) = def.as_object().filter(|t| t.contains_key("type")) else {
+                check_value(name, default, def)?;
+                fixed.insert(name.clone(), def.clone());
+                continue;
+            };
+            let Some(kind) = parse_kind(name, table)? else {
+                let value = table_value(name, def)?;
+                check_value(name, default, &value)?;
+                fixed.insert(name.clone(), value);
+                continue;
+            };
+            check_kind(name, default, &kind)?;
+            let activation = match table.get("active_when") {
+                None => None,
+                Some(when) => {
+                    let when = when
+                        .as_str()
+                        .with_context(|| format!("{name}: `active_when` must be a string"))?;
+                    let active_when = parse_relation(when, &defaults)?;
+                    let inactive_value = table.get("inactive_value").unwrap_or(default).clone();
+                    check_value(name, default, &inactive_value)?;
+                    Some(Activation {
+                        active_when,
+                        inactive_value,
+                    })
+                }
+            };
+            fixed.remove(name);
+            params.push(ParamSpec {
+                name: name.clone(),
+                kind,
+                activation,
+            });
+        }
+        if params.is_empty() {
+            bail!("no parameter to search; give at least one a type other than fixed");
+        }
+
+        let constraints = match root.get("constraints") {
+            None => builtin_constraints(),
+            Some(list) => {
+                let Some(list) = list.as_array() else {
+                    bail!("`constraints` must be a list of strings");
+                };
+                let mut out = Vec::new();
+                for c in list {
+                    let Some(text) = c.as_str() else {
+                        bail!("`constraints` must be a list of strings");
+                    };
+                    let rel = parse_relation(text, &defaults)?;
+                    if rel.is_not_equal() {
+                        bail!("{text:?}: != cannot be used as a constraint");
+                    }
+                    out.push(rel);
+                }
+                out
+            }
+        };
+
+        let mut seeds = Vec::new();
+        for s in root
+            .get("seeds")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let Some(seed) = s.as_object() else {
+                bail!("each seed must be a table of parameter values");
+            };
+            if let Some(name) = seed.keys().find(|k| !defaults.contains_key(*k)) {
+                bail!("seed sets unknown parameter {name}");
+            }
+            seeds.push(seed.clone());
+        }
+
+        let space = Self {
+            params,
+            fixed,
+            constraints,
+            seeds,
+        };
+        for c in &space.constraints {
+            let searched = c
+                .lhs_names()
+                .iter()
+                .any(|n| space.params.iter().any(|s| s.name == *n));
+            if !searched && space.fixed_number_on_left(c).is_none() {
+                bail!(
+                    "{:?}: the left side needs a searched parameter or a fixed number for repair to move",
+                    c.text()
+                );
+            }
+        }
+        Ok(space)
+    }
+
+    /// Pins every non-searched input of `p` to its fixed value.
+    pub fn apply_fixed(&self, p: &mut Map<String, Value>) {
+        for (k, v) in &self.fixed {
+            p.insert(k.clone(), v.clone());
+        }
+    }
+
+    /// Pins inactive parameters, then moves each constraint's target until it holds. The
+    /// target is the first searched int or float parameter on the constraint's left side,
+    /// else the first fixed number there, which is moved past its fixed value as the old
+    /// hand-written rules moved `two_step` up to `step`.
+    pub fn enforce(&self, p: &mut Map<String, Value>) {
+        for s in &self.params {
+            if let Some(a) = &s.activation
+                && !a.active_when.holds(p)
+            {
+                p.insert(s.name.clone(), a.inactive_value.clone());
+            }
+        }
+        for c in &self.constraints {
+            let target = c.lhs_names().iter().find_map(|n| {
+                self.params.iter().find(|s| {
+                    s.name == *n
+                        && matches!(s.kind, ParamKind::Int { .. } | ParamKind::Float { .. })
+                })
+            });
+            if let Some(s) = target {
+                c.repair(p, &s.name, &s.kind);
+            } else if let Some(name) = self.fixed_number_on_left(c) {
+                c.repair_unbounded(p, name);
+            }
+        }
+    }
+
+    /// False when a constraint still fails after `enforce`, e.g. because the target's range
+    /// cannot reach the bound or only bool/categorical parameters are on its left side. Such
+    /// candidates are discarded.
+    pub fn satisfies(&self, p: &Map<String, Value>) -> bool {
+        self.constraints.iter().all(|c| c.holds(p))
+    }
+
+    /// The first fixed int or float input on the left side of `c`.
+    fn fixed_number_on_left<'a>(&self, c: &'a Relation) -> Option<&'a str> {
+        c.lhs_names()
+            .iter()
+            .find(|n| self.fixed.get(n.as_str()).is_some_and(|v| v.is_number()))
+            .map(String::as_str)
+    }
+}
+
+/// The coupling rules of the original EA inputs.
+fn builtin_constraints() -> Vec<Relation> {
+    [
+        "two_min_distance >= min_distance",
+        "two_step >= step",
+        "max_lot >= lot + 0.01",
+        "max_loss >= max_loss_close_all + 5000",
+    ]
+    .iter()
+    .map(|c| Relation::parse(c).expect("valid built-in constraint"))
+    .collect()
+}
+
+/// Parses a relation whose inputs are all known numeric or bool parameters.
+fn parse_relation(text: &str, defaults: &Map<String, Value>) -> Result<Relation> {
+    let rel = Relation::parse(text)?;
+    for name in rel.names() {
+        match defaults.get(name) {
+            None => bail!("{text:?}: unknown parameter {name}"),
+            Some(v) if !v.is_number() && !v.is_boolean() => {
+                bail!("{text:?}: {name} is not a number or bool")
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(rel)
+}
+
+/// Parses a typed entry; `None` for `type = "fixed"`.
+fn parse_kind(name: &str, t: &Map<String, Value>) -> Result<Option<ParamKind>> {
+    let num = |key: &str| {
+        t.get(key)
+            .and_then(Value::as_f64)
+            .with_context(|| format!("{name}: `{key}` must be a number"))
+    };
+    // Whole numbers written as floats (`2.0`, as TOML may) are accepted.
+    let int = |key: &str| {
+        let v = num(key)?;
+        if v.fract() != 0.0 {
+            bail!("{name}: `{key}` must be a whole number for an int parameter, got {v}");
+        }
+        Ok(v as i64)
+    };
+    let kind = match t.get("type").and_then(Value::as_str).unwrap_or("") {
+        "fixed" => return Ok(None),
+        "int" => {
+            let low = int("low")?;
+            let high = int("high")?;
+            let step = if t.contains_key("step") {
+                int("step")?
+            } else {
+                1
+            };
+            if step <= 0 || low > high {
+                bail!("{name}: needs low <= high and step > 0");
+            }
+            ParamKind::Int { low, high, step }
+        }
+        "float" => {
+            let (low, high, step) = (num("low")?, num("high")?, num("step")?);
+            if step <= 0.0 || low > high {
+                bail!("{name}: needs low <= high and step > 0");
+            }
+            let precision = match t.get("precision").and_then(Value::as_u64) {
+                Some(p) => p as u32,
+                None => (-step.log10()).ceil().clamp(0.0, 10.0) as u32,
+            };
+            ParamKind::Float {
+                low,
+                high,
+                step,
+                precision,
+            }
+        }
+        "bool" => {
+            let p_true = t.get("p_true").and_then(Value::as_f64).unwrap_or(0.5);
+            ParamKind::Bool {
+                p_true: p_true.clamp(0.0, 1.0),
+            }
+        }
+        "categorical" => {
+            let values = t
+                .get("values")
+                .and_then(Value::as_array)
+                .filter(|v| !v.is_empty())
+                .with_context(|| format!("{name}: `values` must be a non-empty list"))?;
+            ParamKind::Categorical {
+                values: values.clone(),
+            }
+        }
+        other => bail!("{name}: unknown type {other:?}"),
+    };
+    Ok(Some(kind))
+}
+
+fn table_value(name: &str, def: &Value) -> Result<Value> {
+    def.get("value")
+        .cloned()
+        .with_context(|| format!("{name}: fixed entries need a `value`"))
+}
+
+/// Rejects kinds that cannot produce the input's type, e.g. a float range for a bool.
+fn check_kind(name: &str, default: &Value, kind: &ParamKind) -> Result<()> {
+    let ok = match kind {
+        ParamKind::Int { .. } | ParamKind::Float { .. } => default.is_number(),
+        ParamKind::Bool { .. } => default.is_boolean(),
+        ParamKind::Categorical { values } => values.iter().all(|v| same_type(default, v)),
+    };
+    if !ok {
+        bail!("{name}: search type does not match its default value {default}");
+    }
+    Ok(())
+}
+
+fn check_value(name: &str, default: &Value, value: &Value) -> Result<()> {
+    if !same_type(default, value) {
+        bail!("{name}: {value} does not match the type of its default value {default}");
+    }
+    Ok(())
+}
+
+fn same_type(a: &Value, b: &Value) -> bool {
+    (a.is_number() && b.is_number())
+        || (a.is_boolean() && b.is_boolean())
+        || (a.is_string() && b.is_string())
+}
+
+fn known_names(defaults: &Map<String, Value>) -> String {
+    defaults.keys().cloned().collect::<Vec<_>>().join(", ")
+}
+
+/// Every strategy input the optimizer knows, at its `Amazing3.1.mq4.bak` default.
+fn mq4_defaults() -> Map<String, Value> {
+    let mut p = Map::new();
+    p.insert("on_top_not_buy_first".to_string(), Value::from(0.0));
+    p.insert("on_under_not_sell_first".to_string(), Value::from(0.0));
+    p.insert("on_top_not_buy_add".to_string(), Value::from(0.0));
+    p.insert("on_under_not_sell_add".to_string(), Value::from(0.0));
+
+    p.insert("limit_start_time".to_string(), Value::from("00:00"));
+    p.insert("limit_stop_time".to_string(), Value::from("24:00"));
+    p.insert("ea_start_time".to_string(), Value::from("00:00"));
+    p.insert("ea_stop_time".to_string(), Value::from("24:00"));
+
+    p.insert("close_buy_sell".to_string(), Value::from(true));
+    p.insert("homeopathy_close_all".to_string(), Value::from(true));
+    p.insert("homeopathy".to_string(), Value::from(false));
+    p.insert("over".to_string(), Value::from(false));
+    p.insert("next_time".to_string(), Value::from(0));
+    p.insert("money".to_string(), Value::from(0.0));
+
+    p.insert("first_step".to_string(), Value::from(30));
+    p.insert("min_distance".to_string(), Value::from(60));
+    p.insert("two_min_distance".to_string(), Value::from(60));
+    p.insert("step_trail_orders".to_string(), Value::from(5));
+    p.insert("step".to_string(), Value::from(100));
+    p.insert("two_step".to_string(), Value::from(100));
+
+    p.insert("max_loss".to_string(), Value::from(100_000.0));
+    p.insert("max_loss_close_all".to_string(), Value::from(50.0));
+    p.insert("lot".to_string(), Value::from(0.01));
+    p.insert("max_lot".to_string(), Value::from(10.0));
+    p.insert("plus_lot".to_string(), Value::from(0.0));
+    p.insert("k_lot".to_string(), Value::from(1.3));
+    p.insert("digits_lot".to_string(), Value::from(2));
+    p.insert("close_all".to_string(), Value::from(0.5));
+    p.insert("profit_by_count".to_string(), Value::from(true));
+    p.insert("stop_profit".to_string(), Value::from(2.0));
+
+    p.insert("totals".to_string(), Value::from(50));
+    p.insert("max_spread".to_string(), Value::from(32));
+    p.insert("leverage_min".to_string(), Value::from(100));
+
+    p.insert("open_mode".to_string(), Value::from(3));
+    p.insert("time_zone".to_string(), Value::from(1));
+    p.insert("sleep_seconds".to_string(), Value::from(30));
+    p.insert("stop_loss".to_string(), Value::from(0.0));
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn int_kind(table: Value) -> Result<Option<ParamKind>> {
+        parse_kind("step", table.as_object().unwrap())
+    }
+
+    #[test]
+    fn int_step_must_be_a_whole_number() {
+        let steps = |table| match int_kind(table) {
+            Ok(Some(ParamKind::Int { low, high, step })) => Some((low, high, step)),
+            _ => None,
+        };
+        assert_eq!(
+            steps(json!({ "type": "int", "low": 70, "high": 340, "step": 5 })),
+            Some((70, 340, 5))
+        );
+        assert_eq!(
+            steps(json!({ "type": "int", "low": 70.0, "high": 340, "step": 2.0 })),
+            Some((70, 340, 2))
+        );
+        assert_eq!(
+            steps(json!({ "type": "int", "low": 70, "high": 340 })),
+            Some((70, 340, 1))
+        );
+        for bad in [
+            json!({ "type": "int", "low": 70, "high": 340, "step": 0.5 }),
+            json!({ "type": "int", "low": 70, "high": 340, "step": 0 }),
+            json!({ "type": "int", "low": 70, "high": 340, "step": "5" }),
+            json!({ "type": "int", "low": 70.5, "high": 340, "step": 5 }),
+        ] {
+            assert!(int_kind(bad.clone()).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn loads_the_example_toml() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../search_space.example.toml");
+        let space = SearchSpace::load(&path).unwrap();
+
+        let names: Vec<_> = space.params.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "homeopathy",
+                "k_lot",
+                "lot",
+                "money",
+                "step",
+                "time_zone",
+                "two_step"
+            ]
+        );
+        let step = space.params.iter().find(|p| p.name == "step").unwrap();
+        assert!(matches!(
+            step.kind,
+            ParamKind::Int {
+                low: 70,
+                high: 340,
+                step: 5
+            }
+        ));
+        assert_eq!(space.fixed["open_mode"], json!(3));
+        assert_eq!(space.fixed["max_spread"], json!(40));
+        assert_eq!(space.constraints.len(), 4);
+        assert_eq!(space.seeds.len(), 1);
+    }
+
+    #[test]
+    fn loads_json_and_reports_bad_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_path = dir.path().join("space.json");
+        fs::write(
+            &json_path,
+            r#"{
+                "constraints": [],
+                "params": {
+                    "step": { "type": "int", "low": 50, "high": 150, "step": 10 },
+                    "lot": 0.02
+                }
+            }"#,
+        )
+        .unwrap();
+        let space = SearchSpace::load(&json_path).unwrap();
+        assert_eq!(space.params.len(), 1);
+        assert!(matches!(
+            space.params[0].kind,
+            ParamKind::Int {
+                low: 50,
+                high: 150,
+                step: 10
+            }
+        ));
+        assert_eq!(space.fixed["lot"], json!(0.02));
+        assert!(space.constraints.is_empty());
+
+        let toml_path = dir.path().join("space.toml");
+        fs::write(
+            &toml_path,
+            "[params]\nstep = { type = \"int\", low = 50, high = 150, step = 0.5 }\n",
+        )
+        .unwrap();
+        let err = format!("{:#}", SearchSpace::load(&toml_path).unwrap_err());
+        assert!(err.contains("`step` must be a whole number"), "{err}");
+
+        let yaml_path = dir.path().join("space.yaml");
+        fs::write(&yaml_path, "params: {}\n").unwrap();
+        assert!(SearchSpace::load(&yaml_path).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use chrono::{DateTime, Datelike, NaiveDate, Weekday};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::sim_broker::Bar;
+use crate::tick_data::Tick;
+
+/// Daylight-saving rules a broker server clock can follow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DstRule {
+    None,
+    /// New York: second Sunday of March to first Sunday of November (first Sunday of April
+    /// to last Sunday of October before 2007), switching at 02:00 local time.
+    Us,
+    /// Europe: last Sunday of March to last Sunday of October, switching at 01:00 UTC.
+    Eu,
+}
+
+/// The broker's server clock: a fixed offset from UTC, one hour further ahead while DST is
+/// in effect. MT4 reports `TimeCurrent`, bar times and order times on this clock.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ServerTime {
+    pub utc_offset_hours: f64,
+    pub dst: DstRule,
+}
+
+impl ServerTime {
+    pub fn is_utc(self) -> bool {
+        self.utc_offset_hours == 0.0 && self.dst == DstRule::None
+    }
+
+    /// Seconds the server clock is ahead of UTC at `utc_ts`.
+    pub fn offset_at(self, utc_ts: i64) -> i64 {
+        let base = (self.utc_offset_hours * 3600.0).round() as i64;
+        if self.in_dst(utc_ts) {
+            base + 3600
+        } else {
+            base
+        }
+    }
+
+    pub fn to_server(self, utc_ts: i64) -> i64 {
+        utc_ts + self.offset_at(utc_ts)
+    }
+
+    /// Inverse of `to_server`. DST switches fall on the weekend close, so the hour they make
+    /// ambiguous holds no data.
+    pub fn to_utc(self, server_ts: i64) -> i64 {
+        let guess = server_ts - self.offset_at(server_ts);
+        server_ts - self.offset_at(guess)
+    }
+
+    /// Moves UTC bar times onto the server clock.
+    pub fn shift_bars(self, bars: &mut [Bar]) {
+        for b in bars {
+            b.ts = self.to_server(b.ts);
+        }
+    }
+
+    /// Moves UTC tick times onto the server clock, keeping their milliseconds.
+    pub fn shift_ticks(self, ticks: &mut [Tick]) {
+        for t in ticks {
+            let ts = t.ts();
+            t.ts_ms += (self.to_server(ts) - ts) * 1000;
+        }
+    }
+
+    fn in_dst(self, utc_ts: i64) -> bool {
+        let Some(year) = DateTime::from_timestamp(utc_ts, 0).map(|dt| dt.year()) else {
+            return false;
+        };
+        let (start, end) = match self.dst {
+            DstRule::None => return false,
+            DstRule::Us if year >= 2007 => (
+                at_hour(nth_sunday(year, 3, 2), 7),
+                at_hour(nth_sunday(year, 11, 1), 6),
+            ),
+            DstRule::Us => (
+                at_hour(nth_sunday(year, 4, 1), 7),
+                at_hour(last_sunday(year, 10), 6),
+            ),
+            DstRule::Eu => (
+                at_hour(last_sunday(year, 3), 1),
+                at_hour(last_sunday(year, 10), 1),
+            ),
+        };
+        start <= utc_ts && utc_ts < end
+    }
+}
+
+fn nth_sunday(year: i32, month: u32, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Sun, n).expect("valid sunday")
+}
+
+fn last_sunday(year: i32, month: u32) -> NaiveDate {
+    let mut d =
+        NaiveDate::from_ymd_opt(year, month + 1, 1).expect("valid month") - chrono::Days::new(1);
+    while d.weekday() != Weekday::Sun {
+        d = d.pred_opt().expect("valid date");
+    }
+    d
+}
+
+/// `date` at `hour`:00 UTC as a timestamp.
+fn at_hour(date: NaiveDate, hour: u32) -> i64 {
+    date.and_hms_opt(hour, 0, 0)
+        .expect("valid hour")
+        .and_utc()
+        .timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32) -> i64 {
+        at_hour(NaiveDate::from_ymd_opt(y, m, d).expect("valid date"), h)
+    }
+
+    fn clock(dst: DstRule) -> ServerTime {
+        ServerTime {
+            utc_offset_hours: 2.0,
+            dst,
+        }
+    }
+
+    /// Checks that DST starts at `start` and ends at `end` (UTC), to the second.
+    fn assert_dst(dst: DstRule, start: i64, end: i64) {
+        let c = clock(dst);
+        assert_eq!(c.offset_at(start - 1), 7200);
+        assert_eq!(c.offset_at(start), 10800);
+        assert_eq!(c.offset_at(end - 1), 10800);
+        assert_eq!(c.offset_at(end), 7200);
+    }
+
+    #[test]
+    fn us_rules_before_2007() {
+        // First Sunday of April to last Sunday of October, 02:00 New York time.
+        assert_dst(DstRule::Us, utc(2006, 4, 2, 7), utc(2006, 10, 29, 6));
+    }
+
+    #[test]
+    fn us_rules_from_2007() {
+        // Second Sunday of March to first Sunday of November.
+        assert_dst(DstRule::Us, utc(2007, 3, 11, 7), utc(2007, 11, 4, 6));
+    }
+
+    #[test]
+    fn eu_rules() {
+        assert_dst(DstRule::Eu, utc(2006, 3, 26, 1), utc(2006, 10, 29, 1));
+        assert_dst(DstRule::Eu, utc(2007, 3, 25, 1), utc(2007, 10, 28, 1));
+    }
+
+    #[test]
+    fn no_dst_keeps_the_fixed_offset() {
+        let c = clock(DstRule::None);
+        assert_eq!(c.offset_at(utc(2007, 7, 1, 0)), 7200);
+        assert!(!c.is_utc());
+        assert!(
+            ServerTime {
+                utc_offset_hours: 0.0,
+                dst: DstRule::None
+            }
+            .is_utc()
+        );
+    }
+
+    #[test]
+    fn to_utc_inverts_to_server() {
+        for dst in [DstRule::None, DstRule::Us, DstRule::Eu] {
+            let c = clock(dst);
+            // Either side of each switch and mid-summer.
+            for ts in [
+                utc(2007, 3, 9, 12),
+                utc(2007, 3, 12, 12),
+                utc(2007, 3, 30, 12),
+                utc(2007, 7, 4, 12),
+                utc(2007, 10, 26, 12),
+                utc(2007, 10, 30, 12),
+                utc(2007, 11, 6, 12),
+            ] {
+                assert_eq!(c.to_utc(c.to_server(ts)), ts, "{dst:?} at {ts}");
+            }
+        }
+    }
+}
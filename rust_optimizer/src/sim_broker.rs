@@ -0,0 +1,1207 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Datelike, Weekday};
+use clap::ValueEnum;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use crate::amazing31_mt4::{BrokerApi, OrderSnapshot, OrderType, TradeError};
+use crate::currency::QuoteConversion;
+use crate::execution::{SlippageModel, SlippageModelKind, SpreadModel, SpreadModelKind};
+use crate::server_time::ServerTime;
+use crate::symbol_spec::SymbolSpec;
+use crate::tick_data::Tick;
+use crate::{MAGIC, round_to, ts_to_utc};
+
+/// `repr(C)` so the bar cache can hand out its records as bars without copying them.
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct Bar {
+    pub ts: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    /// Recorded spread in points; NaN when the data carries none.
+    pub spread: f64,
+}
+
+impl Bar {
+    /// The recorded spread in points, when the data carries one.
+    pub fn spread(&self) -> Option<f64> {
+        (!self.spread.is_nan()).then_some(self.spread)
+    }
+}
+
+/// Seconds covered by one input bar (the loaders only produce M5 data).
+pub const BAR_SECONDS: i64 = 300;
+
+/// How prices move inside a bar between its open and close.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PricePath {
+    /// One tick at the close; pending orders trigger from the bar's high/low.
+    Close,
+    /// Four ticks: O-L-H-C for an up bar, O-H-L-C for a down bar.
+    Ohlc,
+    /// `ticks_per_bar` ticks interpolated along the O-H-L-C / O-L-H-C path, like MT4's
+    /// "control points" model.
+    ControlPoints,
+    /// Recorded bid/ask ticks from `--tick-file`; chosen automatically, not from the CLI.
+    #[value(skip)]
+    Ticks,
+}
+
+/// Unit of `SymbolSpec::swap_long` / `swap_short`, as in MT4's `SYMBOL_SWAP_MODE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SwapMode {
+    /// Points per lot per night, converted to money at the current quote.
+    Points,
+    /// Account money per lot per night.
+    Money,
+}
+
+/// How `SimConfig::commission` is charged on each side of a trade.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommissionMode {
+    /// Account money per lot per side.
+    PerLot,
+    /// Account money per million of notional per side.
+    PerMillion,
+    /// Percent of notional per side.
+    Percent,
+}
+
+/// Unit of the margin-call and stop-out levels, as in MT4's `ACCOUNT_MARGIN_SO_MODE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StopOutMode {
+    /// Margin level, i.e. equity / used margin * 100.
+    Percent,
+    /// Equity in account money.
+    Money,
+}
+
+/// A margin call or forced close, logged in the order the broker raised it.
+#[derive(Clone, Debug, Serialize)]
+pub struct StopOutEvent {
+    pub time_utc: String,
+    pub kind: &'static str,
+    /// Margin level (percent) or equity (money) that triggered the event.
+    pub level: f64,
+    pub equity: f64,
+    /// Position closed by a stop-out; zero for margin calls.
+    pub ticket: i64,
+    pub lots: f64,
+    pub realized: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SimConfig {
+    pub price_path: PricePath,
+    pub ticks_per_bar: usize,
+    /// Recorded quotes replayed in `PricePath::Ticks` mode.
+    #[serde(skip)]
+    pub ticks: Arc<Vec<Tick>>,
+
+    pub spec: SymbolSpec,
+
+    pub spread_model: SpreadModelKind,
+    /// Spread for `SpreadModelKind::Fixed`, in pips.
+    pub spread_pips: f64,
+    pub slippage_model: SlippageModelKind,
+    /// Slippage for `SlippageModelKind::Fixed`, in pips.
+    pub slippage_pips: f64,
+    /// Server hour (0-23) at which the daily rollover is charged.
+    pub rollover_hour: u32,
+    /// Clock the bar and tick times run on; results are reported back in UTC.
+    pub server_time: ServerTime,
+
+    pub commission: f64,
+    pub commission_mode: CommissionMode,
+
+    /// Level below which a margin call is logged; 0 disables.
+    pub margin_call_level: f64,
+    /// Level below which losing positions are force-closed, worst first; 0 disables.
+    pub stop_out_level: f64,
+    pub stop_out_mode: StopOutMode,
+
+    /// Deposit currency that balance, equity, margin and P&L are expressed in.
+    pub account_currency: String,
+    pub quote_conversion: QuoteConversion,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimBroker {
+    cfg: SimConfig,
+    symbol: String,
+    pub balance: f64,
+    pub equity: f64,
+    leverage: i32,
+
+    spread_model: Arc<dyn SpreadModel>,
+    slippage_model: Arc<dyn SlippageModel>,
+
+    orders: Vec<OrderSnapshot>,
+    next_ticket: i64,
+
+    current_bar: Option<Bar>,
+    /// No quote of the current bar's path has arrived yet.
+    at_bar_open: bool,
+    now_ts: i64,
+    bid: f64,
+    ask: f64,
+    spread_points: f64,
+
+    bar_worst_equity: f64,
+    bar_worst_free_margin: f64,
+
+    rollover_day: Option<i64>,
+    pub swap_total: f64,
+    pub commission_total: f64,
+
+    /// Trade requests refused by the server-side checks (stop/freeze level, volume, ticket).
+    pub rejected_requests: usize,
+
+    in_margin_call: bool,
+    pub stop_out_events: Vec<StopOutEvent>,
+
+    pub closed_pnls: Vec<f64>,
+    pub equity_curve: Vec<f64>,
+    pub worst_equity_curve: Vec<f64>,
+    pub balance_curve: Vec<f64>,
+    pub spread_pips_curve: Vec<f64>,
+
+    rng: StdRng,
+}
+
+impl SimBroker {
+    pub fn new(
+        cfg: &SimConfig,
+        symbol: &str,
+        initial_balance: f64,
+        leverage: i32,
+        seed: u64,
+    ) -> Self {
+        Self {
+            cfg: cfg.clone(),
+            symbol: symbol.to_string(),
+            balance: initial_balance,
+            equity: initial_balance,
+            leverage,
+            spread_model: cfg.spread_model.build(cfg.spread_pips),
+            slippage_model: cfg.slippage_model.build(cfg.slippage_pips),
+            orders: Vec::new(),
+            next_ticket: 1,
+            current_bar: None,
+            at_bar_open: false,
+            now_ts: 0,
+            bid: 0.0,
+            ask: 0.0,
+            spread_points: 0.0,
+            bar_worst_equity: f64::INFINITY,
+            bar_worst_free_margin: f64::INFINITY,
+            rollover_day: None,
+            swap_total: 0.0,
+            commission_total: 0.0,
+            rejected_requests: 0,
+            in_margin_call: false,
+            stop_out_events: Vec::new(),
+            closed_pnls: Vec::new(),
+            equity_curve: Vec::new(),
+            worst_equity_curve: Vec::new(),
+            balance_curve: Vec::new(),
+            spread_pips_curve: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn price_path(&self) -> PricePath {
+        self.cfg.price_path
+    }
+
+    /// Starts a new bar. In `PricePath::Close` mode the quotes jump straight to the close;
+    /// otherwise they stay at the previous tick until `on_tick` walks the bar's path.
+    pub fn on_bar(&mut self, bar: &Bar) {
+        self.current_bar = Some(bar.clone());
+        self.at_bar_open = true;
+        self.now_ts = bar.ts;
+        self.bar_worst_equity = f64::INFINITY;
+        self.bar_worst_free_margin = f64::INFINITY;
+
+        // Tick data carries its own quotes; bars take their spread from the spread model.
+        let points_per_pip = self.cfg.spec.pip() / self.cfg.spec.point;
+        let spread_pips = if self.cfg.price_path == PricePath::Ticks {
+            self.spread_points / points_per_pip
+        } else {
+            let pips = self
+                .spread_model
+                .spread_pips(bar, &self.cfg.spec, &mut self.rng);
+            self.spread_points = pips * points_per_pip;
+            pips
+        };
+        self.spread_pips_curve.push(spread_pips);
+
+        if self.cfg.price_path == PricePath::Close {
+            self.set_quotes(bar.close);
+        }
+        self.accrue_swap(bar.ts);
+    }
+
+    /// Synthetic `(timestamp, mid price)` ticks for `bar` under the configured price path.
+    pub fn bar_ticks(&self, bar: &Bar) -> Vec<(i64, f64)> {
+        let (first, second) = if bar.close >= bar.open {
+            (bar.low, bar.high)
+        } else {
+            (bar.high, bar.low)
+        };
+        let vertices = [bar.open, first, second, bar.close];
+
+        let prices = match self.cfg.price_path {
+            PricePath::Close | PricePath::Ticks => vec![bar.close],
+            PricePath::Ohlc => vertices.to_vec(),
+            PricePath::ControlPoints => interpolate_path(&vertices, self.cfg.ticks_per_bar),
+        };
+
+        let n = prices.len() as i64;
+        prices
+            .into_iter()
+            .enumerate()
+            .map(|(i, px)| (bar.ts + i as i64 * BAR_SECONDS / n, px))
+            .collect()
+    }
+
+    /// Moves the quotes to `mid` with the modelled spread; see `on_quote`.
+    pub fn on_tick(&mut self, ts: i64, mid: f64) {
+        let (bid, ask) = self.quotes_for(mid);
+        self.on_quote(ts, bid, ask);
+    }
+
+    /// Moves the quotes to `bid`/`ask` and fills any stop order the move crossed. Recorded
+    /// ticks are the only prices there were, so in tick mode a stop fills at the quote that
+    /// triggered it. On a synthetic path a stop crossed between two path points fills at its
+    /// own price, while one crossed by the first quote of a bar was gapped over from the
+    /// previous close and fills at that quote, as `trigger_pending_from_bar` fills at the
+    /// open.
+    pub fn on_quote(&mut self, ts: i64, bid: f64, ask: f64) {
+        self.now_ts = ts;
+        let (prev_bid, prev_ask) = (self.bid, self.ask);
+        let fill_at_quote = std::mem::replace(&mut self.at_bar_open, false)
+            || self.cfg.price_path == PricePath::Ticks;
+        self.bid = bid;
+        self.ask = ask;
+        if self.cfg.price_path == PricePath::Ticks {
+            self.spread_points = (ask - bid) / self.cfg.spec.point;
+        }
+        self.accrue_swap(ts);
+
+        for idx in 0..self.orders.len() {
+            let (order_type, open_price) = {
+                let o = &self.orders[idx];
+                (o.order_type, o.open_price)
+            };
+
+            if order_type == OrderType::BuyStop && self.ask >= open_price {
+                let base_fill = if fill_at_quote {
+                    self.ask.max(open_price)
+                } else if prev_ask < open_price {
+                    open_price
+                } else {
+                    self.ask
+                };
+                self.fill_pending(idx, base_fill, ts);
+            } else if order_type == OrderType::SellStop && self.bid <= open_price {
+                let base_fill = if fill_at_quote {
+                    self.bid.min(open_price)
+                } else if prev_bid > open_price {
+                    open_price
+                } else {
+                    self.bid
+                };
+                self.fill_pending(idx, base_fill, ts);
+            }
+        }
+
+        self.mark_to_market();
+        self.check_stop_out();
+        self.track_intrabar_extremes();
+    }
+
+    /// Turns the stop order at `idx` into a market position at `base_fill` plus slippage and
+    /// charges the opening side of the commission.
+    fn fill_pending(&mut self, idx: usize, base_fill: f64, ts: i64) {
+        let (is_buy, lots) = {
+            let o = &self.orders[idx];
+            (o.order_type == OrderType::BuyStop, o.lots)
+        };
+        let fill = round_to(
+            self.apply_slippage_price(base_fill, lots, is_buy),
+            self.cfg.spec.digits,
+        );
+        let fee = self.commission_for(lots, fill);
+        self.commission_total += fee;
+
+        let o = &mut self.orders[idx];
+        o.order_type = if is_buy {
+            OrderType::Buy
+        } else {
+            OrderType::Sell
+        };
+        o.open_price = fill;
+        o.open_time = ts;
+        o.commission -= fee;
+    }
+
+    /// One side of commission for `lots` traded at `price`, as a positive amount.
+    fn commission_for(&self, lots: f64, price: f64) -> f64 {
+        let notional = self.to_account(lots * self.cfg.spec.contract_size * price, price);
+        let fee = match self.cfg.commission_mode {
+            CommissionMode::PerLot => self.cfg.commission * lots,
+            CommissionMode::PerMillion => self.cfg.commission * notional / 1_000_000.0,
+            CommissionMode::Percent => self.cfg.commission / 100.0 * notional,
+        };
+        fee.max(0.0)
+    }
+
+    /// Charges one night of swap on every open position for each rollover passed since the
+    /// last call. Rollovers closing a Saturday or Sunday are skipped and Wednesday's is
+    /// tripled to cover the weekend, as on MT4 servers.
+    fn accrue_swap(&mut self, ts: i64) {
+        let offset = self.cfg.rollover_hour.min(23) as i64 * 3600;
+        let day = (ts - offset).div_euclid(86_400);
+        let Some(last) = self.rollover_day.replace(day) else {
+            return;
+        };
+
+        for d in (last + 1)..=day {
+            let boundary = d * 86_400 + offset;
+            let nights = match DateTime::from_timestamp(boundary - 1, 0).map(|dt| dt.weekday()) {
+                Some(Weekday::Sat | Weekday::Sun) => continue,
+                Some(Weekday::Wed) => 3.0,
+                _ => 1.0,
+            };
+            self.charge_swap(nights);
+        }
+    }
+
+    fn charge_swap(&mut self, nights: f64) {
+        let (bid, ask) = (self.bid, self.ask);
+        let spec = &self.cfg.spec;
+        let per_point = spec.point * spec.contract_size;
+        let conv = &self.cfg.quote_conversion;
+        let mut charged = 0.0;
+        for o in &mut self.orders {
+            let (rate, px) = match o.order_type {
+                OrderType::Buy => (spec.swap_long, bid),
+                OrderType::Sell => (spec.swap_short, ask),
+                _ => continue,
+            };
+            let per_lot = match spec.swap_mode {
+                SwapMode::Money => rate,
+                SwapMode::Points => conv.to_account(rate * per_point, px, self.now_ts),
+            };
+            let swap = per_lot * o.lots * nights;
+            o.swap += swap;
+            charged += swap;
+        }
+        self.swap_total += charged;
+        self.mark_to_market();
+    }
+
+    fn set_quotes(&mut self, mid: f64) {
+        (self.bid, self.ask) = self.quotes_for(mid);
+    }
+
+    /// Bid/ask around `mid` with the current spread.
+    fn quotes_for(&self, mid: f64) -> (f64, f64) {
+        let half_spread = (self.spread_points * self.cfg.spec.point) / 2.0;
+        let digits = self.cfg.spec.digits;
+        (
+            round_to(mid - half_spread, digits),
+            round_to(mid + half_spread, digits),
+        )
+    }
+
+    pub fn trigger_pending_from_bar(&mut self) {
+        let Some(ohlc) = self.current_bar.clone() else {
+            return;
+        };
+
+        for idx in 0..self.orders.len() {
+            let (order_type, open_price) = {
+                let o = &self.orders[idx];
+                (o.order_type, o.open_price)
+            };
+
+            if order_type == OrderType::BuyStop && ohlc.high >= open_price {
+                self.fill_pending(idx, ohlc.open.max(open_price), ohlc.ts);
+            } else if order_type == OrderType::SellStop && ohlc.low <= open_price {
+                self.fill_pending(idx, ohlc.open.min(open_price), ohlc.ts);
+            }
+        }
+
+        self.mark_to_market();
+        self.stop_out_at_bar_extreme(&ohlc);
+        self.track_intrabar_extremes();
+    }
+
+    /// `PricePath::Close` has no ticks inside the bar, so the stop-out check runs at whichever
+    /// of the bar's high or low hurts the account most, then the quotes return to the close.
+    fn stop_out_at_bar_extreme(&mut self, bar: &Bar) {
+        if self.cfg.stop_out_level <= 0.0 && self.cfg.margin_call_level <= 0.0 {
+            return;
+        }
+        let close = (self.bid, self.ask);
+        let worst_mid = [bar.low, bar.high]
+            .into_iter()
+            .map(|mid| {
+                let (bid, ask) = self.quotes_for(mid);
+                (mid, self.equity_at(bid, ask))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(mid, _)| mid)
+            .unwrap_or(bar.close);
+
+        self.set_quotes(worst_mid);
+        self.mark_to_market();
+        self.check_stop_out();
+        (self.bid, self.ask) = close;
+        self.mark_to_market();
+    }
+
+    /// Current margin level in `StopOutMode` units, or `None` without open positions.
+    fn stop_out_measure(&self) -> Option<f64> {
+        let used = self.used_margin();
+        if used <= 0.0 {
+            return None;
+        }
+        Some(match self.cfg.stop_out_mode {
+            StopOutMode::Percent => self.equity / used * 100.0,
+            StopOutMode::Money => self.equity,
+        })
+    }
+
+    /// Logs a margin call when the level first drops below `margin_call_level`, then closes
+    /// the position with the largest loss, one at a time, until the level is back above
+    /// `stop_out_level`, as MT4 servers do.
+    fn check_stop_out(&mut self) {
+        let Some(level) = self.stop_out_measure() else {
+            self.in_margin_call = false;
+            return;
+        };
+        if level < self.cfg.margin_call_level {
+            if !self.in_margin_call {
+                self.in_margin_call = true;
+                self.log_stop_out("margin_call", level, 0, 0.0, 0.0);
+            }
+        } else {
+            self.in_margin_call = false;
+        }
+
+        while let Some(level) = self.stop_out_measure()
+            && level < self.cfg.stop_out_level
+        {
+            let Some((ticket, lots)) = self
+                .orders
+                .iter()
+                .filter(|o| matches!(o.order_type, OrderType::Buy | OrderType::Sell))
+                .min_by(|a, b| a.total_profit().total_cmp(&b.total_profit()))
+                .map(|o| (o.ticket, o.lots))
+            else {
+                break;
+            };
+            if self.close_order(ticket).is_err() {
+                break;
+            }
+            let realized = self.closed_pnls.last().copied().unwrap_or(0.0);
+            self.log_stop_out("stop_out", level, ticket, lots, realized);
+        }
+    }
+
+    fn log_stop_out(
+        &mut self,
+        kind: &'static str,
+        level: f64,
+        ticket: i64,
+        lots: f64,
+        realized: f64,
+    ) {
+        self.stop_out_events.push(StopOutEvent {
+            time_utc: ts_to_utc(self.cfg.server_time.to_utc(self.now_ts)),
+            kind,
+            level,
+            equity: self.equity,
+            ticket,
+            lots,
+            realized,
+        });
+    }
+
+    pub fn stop_out_count(&self) -> usize {
+        self.stop_out_events
+            .iter()
+            .filter(|e| e.kind == "stop_out")
+            .count()
+    }
+
+    pub fn margin_call_count(&self) -> usize {
+        self.stop_out_events
+            .iter()
+            .filter(|e| e.kind == "margin_call")
+            .count()
+    }
+
+    pub fn snapshot(&mut self) {
+        self.mark_to_market();
+        self.track_intrabar_extremes();
+        self.equity_curve.push(self.equity);
+        self.balance_curve.push(self.balance);
+        self.worst_equity_curve.push(self.bar_worst_equity);
+    }
+
+    /// Lowest equity the open positions could have reached inside the current bar.
+    pub fn bar_worst_equity(&self) -> f64 {
+        self.bar_worst_equity
+    }
+
+    /// Free margin at the intrabar equity low of the current bar.
+    pub fn bar_worst_free_margin(&self) -> f64 {
+        self.bar_worst_free_margin
+    }
+
+    /// Keeps the worst equity seen so far in this bar. In `PricePath::Close` mode the open
+    /// positions are also revalued at the bar's high and low, quoted with the current spread;
+    /// the tick-driven paths visit those extremes themselves.
+    fn track_intrabar_extremes(&mut self) {
+        let mut worst = self.equity;
+        if self.cfg.price_path == PricePath::Close
+            && let Some(bar) = self.current_bar.as_ref()
+        {
+            for mid in [bar.low, bar.high] {
+                let (bid, ask) = self.quotes_for(mid);
+                worst = worst.min(self.equity_at(bid, ask));
+            }
+        }
+        self.bar_worst_equity = self.bar_worst_equity.min(worst);
+        self.bar_worst_free_margin = self.bar_worst_free_margin.min(worst - self.used_margin());
+    }
+
+    fn equity_at(&self, bid: f64, ask: f64) -> f64 {
+        let floating: f64 = self
+            .orders
+            .iter()
+            .map(|o| {
+                let profit = match o.order_type {
+                    OrderType::Buy => self.pnl_buy(o.lots, o.open_price, bid),
+                    OrderType::Sell => self.pnl_sell(o.lots, o.open_price, ask),
+                    _ => return 0.0,
+                };
+                profit + o.swap + o.commission
+            })
+            .sum();
+        self.balance + floating
+    }
+
+    /// Closes every market order and deletes every pending order, e.g. at the end of a run.
+    /// Pending orders are dropped directly, bypassing the freeze-level check.
+    pub fn close_all(&mut self) {
+        self.orders
+            .retain(|o| matches!(o.order_type, OrderType::Buy | OrderType::Sell));
+        let tickets: Vec<i64> = self.orders.iter().map(|o| o.ticket).collect();
+        for ticket in tickets {
+            let _ = self.close_order(ticket);
+        }
+    }
+
+    fn used_margin(&self) -> f64 {
+        self.orders
+            .iter()
+            .filter(|o| matches!(o.order_type, OrderType::Buy | OrderType::Sell))
+            .map(|o| o.lots * self.margin_per_lot(&self.symbol))
+            .sum()
+    }
+
+    fn mark_to_market(&mut self) {
+        let bid = self.bid;
+        let ask = self.ask;
+        let cfg = &self.cfg;
+        let ts = self.now_ts;
+        let mut floating = 0.0;
+        for o in &mut self.orders {
+            o.profit = match o.order_type {
+                OrderType::Buy => Self::pnl_buy_calc(cfg, ts, o.lots, o.open_price, bid),
+                OrderType::Sell => Self::pnl_sell_calc(cfg, ts, o.lots, o.open_price, ask),
+                _ => 0.0,
+            };
+            floating += o.total_profit();
+        }
+        self.equity = self.balance + floating;
+    }
+
+    /// Moves `price` against a trade that buys (`is_buy`) or sells, by the slippage model.
+    fn apply_slippage_price(&mut self, price: f64, lots: f64, is_buy: bool) -> f64 {
+        let Some(bar) = self.current_bar.as_ref() else {
+            return price;
+        };
+
+        let slip_pips = self
+            .slippage_model
+            .slippage_pips(bar, lots, &self.cfg.spec, &mut self.rng);
+        let slip = slip_pips * self.cfg.spec.pip();
+
+        if is_buy {
+            round_to(price + slip, self.cfg.spec.digits)
+        } else {
+            round_to(price - slip, self.cfg.spec.digits)
+        }
+    }
+
+    /// Distance in points from the market at which a stop order at `price` would trigger:
+    /// buy stops are measured from the ask, sell stops from the bid.
+    fn distance_from_market(&self, order_type: OrderType, price: f64) -> f64 {
+        let gap = match order_type {
+            OrderType::BuyStop => price - self.ask,
+            OrderType::SellStop => self.bid - price,
+            _ => return f64::INFINITY,
+        };
+        gap / self.cfg.spec.point
+    }
+
+    /// Checks a stop-order price against `stop_level`. Like MT4, a price on the wrong side
+    /// of the market is refused even when the stop level is zero.
+    fn check_stop_level(&self, order_type: OrderType, price: f64) -> Result<(), TradeError> {
+        let dist = self.distance_from_market(order_type, price);
+        if dist <= 0.0 || dist < self.cfg.spec.stop_level as f64 - 1e-6 {
+            return Err(TradeError::InvalidStops);
+        }
+        Ok(())
+    }
+
+    fn reject<T>(&mut self, err: TradeError) -> Result<T, TradeError> {
+        self.rejected_requests += 1;
+        Err(err)
+    }
+
+    /// Refuses to touch a pending order within `freeze_level` points of its trigger price.
+    fn check_freeze_level(&self, order_type: OrderType, open_price: f64) -> Result<(), TradeError> {
+        let freeze = self.cfg.spec.freeze_level as f64;
+        if freeze > 0.0 && self.distance_from_market(order_type, open_price) <= freeze {
+            return Err(TradeError::TradeModifyDenied);
+        }
+        Ok(())
+    }
+
+    /// Converts a quote-currency amount realised at `price` into account currency.
+    fn to_account(&self, amount: f64, price: f64) -> f64 {
+        self.cfg
+            .quote_conversion
+            .to_account(amount, price, self.now_ts)
+    }
+
+    fn pnl_buy(&self, lots: f64, open_price: f64, close_bid: f64) -> f64 {
+        Self::pnl_buy_calc(&self.cfg, self.now_ts, lots, open_price, close_bid)
+    }
+
+    fn pnl_buy_calc(cfg: &SimConfig, ts: i64, lots: f64, open_price: f64, close_bid: f64) -> f64 {
+        let units = cfg.spec.contract_size * lots;
+        if close_bid <= 0.0 {
+            return 0.0;
+        }
+        cfg.quote_conversion
+            .to_account(units * (close_bid - open_price), close_bid, ts)
+    }
+
+    fn pnl_sell(&self, lots: f64, open_price: f64, close_ask: f64) -> f64 {
+        Self::pnl_sell_calc(&self.cfg, self.now_ts, lots, open_price, close_ask)
+    }
+
+    fn pnl_sell_calc(cfg: &SimConfig, ts: i64, lots: f64, open_price: f64, close_ask: f64) -> f64 {
+        let units = cfg.spec.contract_size * lots;
+        if close_ask <= 0.0 {
+            return 0.0;
+        }
+        cfg.quote_conversion
+            .to_account(units * (open_price - close_ask), close_ask, ts)
+    }
+}
+
+impl BrokerApi for SimBroker {
+    fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    fn digits(&self) -> i32 {
+        self.cfg.spec.digits
+    }
+
+    fn point(&self) -> f64 {
+        self.cfg.spec.point
+    }
+
+    fn leverage(&self) -> i32 {
+        self.leverage
+    }
+
+    fn spread_points(&self) -> f64 {
+        self.spread_points
+    }
+
+    fn bid_ask(&self) -> (f64, f64) {
+        (self.bid, self.ask)
+    }
+
+    fn free_margin(&self) -> f64 {
+        self.equity - self.used_margin()
+    }
+
+    fn margin_per_lot(&self, symbol: &str) -> f64 {
+        if symbol != self.symbol {
+            return 0.0;
+        }
+        let spec = &self.cfg.spec;
+        let account = &self.cfg.account_currency;
+        // A margin currency equal to the deposit currency (and not the quote) needs no
+        // price: one lot is simply `contract_size` account units.
+        let notional = match &spec.margin_currency {
+            Some(m)
+                if !account.is_empty()
+                    && m.eq_ignore_ascii_case(account)
+                    && !self
+                        .symbol
+                        .to_uppercase()
+                        .ends_with(&account.to_uppercase()) =>
+            {
+                spec.contract_size
+            }
+            _ => self.to_account(spec.contract_size * self.bid, self.bid),
+        };
+        notional / self.leverage as f64
+    }
+
+    fn is_trade_allowed(&self) -> bool {
+        true
+    }
+
+    fn is_expert_enabled(&self) -> bool {
+        true
+    }
+
+    fn is_stopped(&self) -> bool {
+        false
+    }
+
+    fn orders(&self) -> Vec<OrderSnapshot> {
+        self.orders.clone()
+    }
+
+    fn send_pending(
+        &mut self,
+        order_type: OrderType,
+        lots: f64,
+        price: f64,
+        comment: &str,
+    ) -> Result<i64, TradeError> {
+        let Some(lots) = self.cfg.spec.normalize_lots(lots) else {
+            return self.reject(TradeError::InvalidTradeVolume);
+        };
+        let price = round_to(price, self.cfg.spec.digits);
+        if let Err(code) = self.check_stop_level(order_type, price) {
+            return self.reject(code);
+        }
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.orders.push(OrderSnapshot {
+            ticket,
+            symbol: self.symbol.clone(),
+            magic: MAGIC,
+            order_type,
+            lots,
+            open_price: price,
+            profit: 0.0,
+            swap: 0.0,
+            commission: 0.0,
+            comment: comment.to_string(),
+            open_time: self.now_ts,
+        });
+        Ok(ticket)
+    }
+
+    fn modify_order(&mut self, ticket: i64, new_price: f64) -> Result<(), TradeError> {
+        let Some(idx) = self.orders.iter().position(|o| {
+            o.ticket == ticket && matches!(o.order_type, OrderType::BuyStop | OrderType::SellStop)
+        }) else {
+            return self.reject(TradeError::InvalidTicket);
+        };
+        let (order_type, open_price) = (self.orders[idx].order_type, self.orders[idx].open_price);
+        if let Err(e) = self.check_freeze_level(order_type, open_price) {
+            return self.reject(e);
+        }
+        let price = round_to(new_price, self.cfg.spec.digits);
+        if let Err(code) = self.check_stop_level(order_type, price) {
+            return self.reject(code);
+        }
+        self.orders[idx].open_price = price;
+        Ok(())
+    }
+
+    fn close_order(&mut self, ticket: i64) -> Result<(), TradeError> {
+        let Some(idx) = self.orders.iter().position(|o| o.ticket == ticket) else {
+            return self.reject(TradeError::InvalidTicket);
+        };
+        let o = self.orders[idx].clone();
+
+        if matches!(o.order_type, OrderType::BuyStop | OrderType::SellStop) {
+            return self.delete_order(ticket);
+        }
+
+        let (pnl, close_px) = match o.order_type {
+            OrderType::Buy => {
+                let close_px = self.apply_slippage_price(self.bid, o.lots, false);
+                (self.pnl_buy(o.lots, o.open_price, close_px), close_px)
+            }
+            OrderType::Sell => {
+                let close_px = self.apply_slippage_price(self.ask, o.lots, true);
+                (self.pnl_sell(o.lots, o.open_price, close_px), close_px)
+            }
+            _ => (0.0, 0.0),
+        };
+        let close_fee = self.commission_for(o.lots, close_px);
+        self.commission_total += close_fee;
+
+        let realized = pnl + o.swap + o.commission - close_fee;
+        self.balance += realized;
+        self.closed_pnls.push(realized);
+        self.orders.remove(idx);
+        self.mark_to_market();
+        Ok(())
+    }
+
+    fn delete_order(&mut self, ticket: i64) -> Result<(), TradeError> {
+        let Some(idx) = self.orders.iter().position(|o| {
+            o.ticket == ticket && matches!(o.order_type, OrderType::BuyStop | OrderType::SellStop)
+        }) else {
+            return self.reject(TradeError::InvalidTicket);
+        };
+        let o = &self.orders[idx];
+        if let Err(e) = self.check_freeze_level(o.order_type, o.open_price) {
+            return self.reject(e);
+        }
+        self.orders.remove(idx);
+        Ok(())
+    }
+}
+
+/// Spreads `total` ticks over the legs between `vertices`, proportionally to each leg's
+/// length, always keeping the vertices themselves.
+fn interpolate_path(vertices: &[f64], total: usize) -> Vec<f64> {
+    let legs = vertices.len().saturating_sub(1);
+    if legs == 0 {
+        return vertices.to_vec();
+    }
+    let extra = total.saturating_sub(vertices.len());
+    let lengths: Vec<f64> = vertices.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+    let path_len: f64 = lengths.iter().sum();
+
+    let mut per_leg: Vec<usize> = if path_len > 0.0 {
+        lengths
+            .iter()
+            .map(|l| (extra as f64 * l / path_len).floor() as usize)
+            .collect()
+    } else {
+        vec![0; legs]
+    };
+    let mut left = extra - per_leg.iter().sum::<usize>();
+    let mut i = 0;
+    while left > 0 {
+        per_leg[i % legs] += 1;
+        left -= 1;
+        i += 1;
+    }
+
+    let mut out = vec![vertices[0]];
+    for (leg, w) in vertices.windows(2).enumerate() {
+        let k = per_leg[leg];
+        for j in 1..=k {
+            out.push(w[0] + (w[1] - w[0]) * j as f64 / (k + 1) as f64);
+        }
+        out.push(w[1]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_time::DstRule;
+
+    const T0: i64 = 1_704_153_600; // Tuesday 2024-01-02 00:00 UTC
+
+    /// A 5-digit symbol quoted in the account currency with a fixed one-pip spread and no
+    /// slippage, commission, swap or stop-out; each test turns on what it exercises.
+    fn config() -> SimConfig {
+        SimConfig {
+            price_path: PricePath::Ohlc,
+            ticks_per_bar: 4,
+            ticks: Arc::new(Vec::new()),
+            spec: SymbolSpec::default(),
+            spread_model: SpreadModelKind::Fixed,
+            spread_pips: 1.0,
+            slippage_model: SlippageModelKind::Zero,
+            slippage_pips: 0.0,
+            rollover_hour: 0,
+            server_time: ServerTime {
+                utc_offset_hours: 0.0,
+                dst: DstRule::None,
+            },
+            commission: 0.0,
+            commission_mode: CommissionMode::PerLot,
+            margin_call_level: 0.0,
+            stop_out_level: 0.0,
+            stop_out_mode: StopOutMode::Percent,
+            account_currency: "USD".to_string(),
+            quote_conversion: QuoteConversion::Unit,
+        }
+    }
+
+    fn bar(ts: i64, open: f64, high: f64, low: f64, close: f64) -> Bar {
+        Bar {
+            ts,
+            open,
+            high,
+            low,
+            close,
+            spread: f64::NAN,
+        }
+    }
+
+    /// A broker quoting `1.10000/1.10010` inside a flat bar at `ts`.
+    fn broker_at(cfg: &SimConfig, ts: i64) -> SimBroker {
+        let mut b = SimBroker::new(cfg, "EURUSD", 10_000.0, 100, 1);
+        b.on_bar(&bar(ts, 1.10005, 1.10005, 1.10005, 1.10005));
+        b.on_quote(ts, 1.10000, 1.10010);
+        b
+    }
+
+    /// Opens a market position by placing a stop 10 points away and quoting through it
+    /// mid-bar, so it fills at its own price plus slippage.
+    fn open(b: &mut SimBroker, order_type: OrderType, lots: f64) -> i64 {
+        let (bid, ask) = b.bid_ask();
+        let (ticket, quote) = match order_type {
+            OrderType::Buy => (
+                b.send_pending(OrderType::BuyStop, lots, ask + 0.00010, ""),
+                (bid + 0.00015, ask + 0.00015),
+            ),
+            _ => (
+                b.send_pending(OrderType::SellStop, lots, bid - 0.00010, ""),
+                (bid - 0.00015, ask - 0.00015),
+            ),
+        };
+        b.on_quote(b.now_ts, quote.0, quote.1);
+        ticket.unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn close_path_tracks_equity_at_the_bar_low() {
+        let cfg = SimConfig {
+            price_path: PricePath::Close,
+            ..config()
+        };
+        let mut b = broker_at(&cfg, T0);
+        open(&mut b, OrderType::Buy, 1.0);
+
+        // Long from 1.10020: the close quotes a 1.10025 bid, the low a 1.09915 bid.
+        b.on_bar(&bar(T0 + 300, 1.10020, 1.10040, 1.09920, 1.10030));
+        b.trigger_pending_from_bar();
+        b.snapshot();
+        assert_close(b.equity, 10_005.0);
+        assert_close(b.bar_worst_equity(), 9_895.0);
+        assert_close(b.worst_equity_curve[0], 9_895.0);
+    }
+
+    #[test]
+    fn stops_gapped_over_fill_at_the_open() {
+        let gap_up = bar(T0 + 300, 1.10100, 1.10150, 1.10080, 1.10120);
+        let gap_down = bar(T0 + 300, 1.09900, 1.09920, 1.09850, 1.09880);
+
+        // Synthetic path: the first quote of the new bar fills the stop.
+        let mut b = broker_at(&config(), T0);
+        b.send_pending(OrderType::BuyStop, 1.0, 1.10030, "")
+            .unwrap();
+        b.on_bar(&gap_up);
+        b.on_quote(gap_up.ts, 1.10095, 1.10105);
+        assert_eq!(b.orders()[0].order_type, OrderType::Buy);
+        assert_close(b.orders()[0].open_price, 1.10105);
+
+        let mut b = broker_at(&config(), T0);
+        b.send_pending(OrderType::SellStop, 1.0, 1.09980, "")
+            .unwrap();
+        b.on_bar(&gap_down);
+        b.on_quote(gap_down.ts, 1.09900, 1.09910);
+        assert_eq!(b.orders()[0].order_type, OrderType::Sell);
+        assert_close(b.orders()[0].open_price, 1.09900);
+
+        // Close path: the bar's open is the fill.
+        let cfg = SimConfig {
+            price_path: PricePath::Close,
+            ..config()
+        };
+        let mut b = broker_at(&cfg, T0);
+        b.send_pending(OrderType::BuyStop, 1.0, 1.10030, "")
+            .unwrap();
+        b.send_pending(OrderType::SellStop, 1.0, 1.09980, "")
+            .unwrap();
+        b.on_bar(&gap_up);
+        b.trigger_pending_from_bar();
+        let orders = b.orders();
+        assert_eq!(orders[0].order_type, OrderType::Buy);
+        assert_close(orders[0].open_price, 1.10100);
+        assert_eq!(orders[1].order_type, OrderType::SellStop);
+    }
+
+    #[test]
+    fn swap_triples_on_wednesday_and_skips_weekends() {
+        let mut cfg = config();
+        cfg.spec.swap_mode = SwapMode::Money;
+        cfg.spec.swap_long = -1.0;
+
+        // Rollovers at 00:00 closing Tue, Wed (x3), Thu, Fri, Sat, Sun and Mon.
+        let mut b = broker_at(&cfg, T0);
+        open(&mut b, OrderType::Buy, 2.0);
+        let mut swaps = Vec::new();
+        for day in 1..=7 {
+            b.on_quote(T0 + day * 86_400 + 3600, 1.10015, 1.10025);
+            swaps.push(b.orders()[0].swap);
+        }
+        assert_eq!(swaps, [-2.0, -8.0, -10.0, -12.0, -12.0, -12.0, -14.0]);
+
+        // A quote a week later catches up on every rollover it skipped.
+        let mut b = broker_at(&cfg, T0);
+        open(&mut b, OrderType::Buy, 2.0);
+        b.on_quote(T0 + 7 * 86_400 + 3600, 1.10015, 1.10025);
+        assert_eq!(b.orders()[0].swap, -14.0);
+        assert_eq!(b.swap_total, -14.0);
+    }
+
+    #[test]
+    fn commission_is_charged_on_each_side() {
+        // 3.5 per lot per side on 2 lots, filled at 1.10020 and closed at 1.10015.
+        let cfg = SimConfig {
+            commission: 3.5,
+            ..config()
+        };
+        let mut b = broker_at(&cfg, T0);
+        let ticket = open(&mut b, OrderType::Buy, 2.0);
+        assert_close(b.orders()[0].commission, -7.0);
+        assert_close(b.commission_total, 7.0);
+        b.close_order(ticket).unwrap();
+        assert_close(b.commission_total, 14.0);
+        assert_close(b.closed_pnls[0], -10.0 - 14.0);
+        assert_close(b.balance, 10_000.0 - 24.0);
+
+        // 5 per million of notional per side, valued at each side's own price.
+        let cfg = SimConfig {
+            commission: 5.0,
+            commission_mode: CommissionMode::PerMillion,
+            ..config()
+        };
+        let mut b = broker_at(&cfg, T0);
+        let ticket = open(&mut b, OrderType::Buy, 1.0);
+        assert_close(b.orders()[0].commission, -5.0 * 0.110020);
+        b.close_order(ticket).unwrap();
+        assert_close(b.commission_total, 5.0 * (0.110020 + 0.110015));
+    }
+
+    #[test]
+    fn stop_out_closes_the_worst_loser_and_trading_goes_on() {
+        let cfg = SimConfig {
+            margin_call_level: 100.0,
+            stop_out_level: 50.0,
+            ..config()
+        };
+        let mut b = broker_at(&cfg, T0);
+        let first = open(&mut b, OrderType::Buy, 4.0);
+        let worst = open(&mut b, OrderType::Buy, 4.0);
+        assert_close(b.orders()[1].open_price, 1.10035);
+
+        // 80 pips down: equity 3540 on 8737.6 margin (40.5%). Closing the later, dearer buy
+        // leaves 3540 on 4368.8 (81%), so the other one stays open.
+        b.on_quote(T0 + 60, 1.09220, 1.09230);
+        let kinds: Vec<_> = b.stop_out_events.iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, ["margin_call", "stop_out"]);
+        assert_eq!(b.stop_out_events[1].ticket, worst);
+        assert_close(b.closed_pnls[0], 400_000.0 * (1.09220 - 1.10035));
+        assert_eq!(b.orders().len(), 1);
+        assert_eq!(b.orders()[0].ticket, first);
+
+        // The account keeps trading on what is left.
+        b.on_quote(T0 + 120, 1.10015, 1.10025);
+        assert!(b.send_pending(OrderType::BuyStop, 1.0, 1.10100, "").is_ok());
+        assert_eq!(b.stop_out_count(), 1);
+        assert_close(b.equity, 10_000.0 - 3_260.0 - 20.0);
+    }
+
+    #[test]
+    fn stops_inside_the_stop_level_are_refused() {
+        let mut cfg = config();
+        cfg.spec.stop_level = 20;
+        cfg.spec.freeze_level = 5;
+        let mut b = broker_at(&cfg, T0);
+
+        // Quotes 1.10000/1.10010: buy stops are measured from the ask, sell stops from the bid.
+        assert_eq!(
+            b.send_pending(OrderType::BuyStop, 1.0, 1.10020, ""),
+            Err(TradeError::InvalidStops)
+        );
+        assert_eq!(
+            b.send_pending(OrderType::SellStop, 1.0, 1.09990, ""),
+            Err(TradeError::InvalidStops)
+        );
+        let ticket = b
+            .send_pending(OrderType::BuyStop, 1.0, 1.10030, "")
+            .unwrap();
+        assert_eq!(
+            b.modify_order(ticket, 1.10025),
+            Err(TradeError::InvalidStops)
+        );
+        assert_close(b.orders()[0].open_price, 1.10030);
+
+        // Within the freeze level the order can be neither moved nor deleted.
+        b.on_quote(T0 + 60, 1.10016, 1.10026);
+        assert_eq!(
+            b.modify_order(ticket, 1.10100),
+            Err(TradeError::TradeModifyDenied)
+        );
+        assert_eq!(b.delete_order(ticket), Err(TradeError::TradeModifyDenied));
+        assert_eq!(b.rejected_requests, 5);
+    }
+
+    #[test]
+    fn closing_slips_against_the_position() {
+        let cfg = SimConfig {
+            slippage_model: SlippageModelKind::Fixed,
+            slippage_pips: 0.5,
+            ..config()
+        };
+
+        // Fills at 1.10020 + 0.00005; closes at the 1.10015 bid less 0.00005.
+        let mut b = broker_at(&cfg, T0);
+        let ticket = open(&mut b, OrderType::Buy, 1.0);
+        assert_close(b.orders()[0].open_price, 1.10025);
+        b.close_order(ticket).unwrap();
+        assert_close(b.closed_pnls[0], 100_000.0 * (1.10010 - 1.10025));
+
+        // Fills at 1.09990 - 0.00005; closes at the 1.09995 ask plus 0.00005.
+        let mut b = broker_at(&cfg, T0);
+        let ticket = open(&mut b, OrderType::Sell, 1.0);
+        assert_close(b.orders()[0].open_price, 1.09985);
+        b.close_order(ticket).unwrap();
+        assert_close(b.closed_pnls[0], 100_000.0 * (1.09985 - 1.10000));
+    }
+}
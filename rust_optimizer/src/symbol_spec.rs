@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::sim_broker::SwapMode;
+
+/// Contract specification of one symbol, mirroring MT4's `MarketInfo` / symbol properties.
+/// Missing keys in the spec file fall back to a 5-digit FX pair.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SymbolSpec {
+    pub digits: i32,
+    pub point: f64,
+    pub contract_size: f64,
+    pub tick_size: f64,
+    /// Account money per `tick_size` move for one lot, if the broker publishes a fixed one.
+    pub tick_value: Option<f64>,
+    pub min_lot: f64,
+    pub max_lot: f64,
+    pub lot_step: f64,
+    /// Minimum distance in points between the market and a pending order price.
+    pub stop_level: i32,
+    /// Distance in points within which a pending order can no longer be modified.
+    pub freeze_level: i32,
+    pub margin_currency: Option<String>,
+    pub swap_long: f64,
+    pub swap_short: f64,
+    pub swap_mode: SwapMode,
+}
+
+impl Default for SymbolSpec {
+    fn default() -> Self {
+        Self {
+            digits: 5,
+            point: 0.00001,
+            contract_size: 100_000.0,
+            tick_size: 0.00001,
+            tick_value: None,
+            min_lot: 0.01,
+            max_lot: 100.0,
+            lot_step: 0.01,
+            stop_level: 0,
+            freeze_level: 0,
+            margin_currency: None,
+            swap_long: 0.0,
+            swap_short: 0.0,
+            swap_mode: SwapMode::Points,
+        }
+    }
+}
+
+impl SymbolSpec {
+    /// Spec used when `symbol` is not in the spec file: a standard FX pair, with 3 digits
+    /// when it is quoted in JPY.
+    pub fn fallback(symbol: &str) -> Self {
+        let mut spec = Self::default();
+        if symbol.to_uppercase().ends_with("JPY") {
+            spec.digits = 3;
+            spec.point = 0.001;
+            spec.tick_size = 0.001;
+        }
+        spec
+    }
+
+    /// Looks `symbol` up (case-insensitively) in a JSON object of `{ "SYMBOL": { ... } }`.
+    /// Returns `None` when the file does not exist or does not list the symbol.
+    pub fn load(path: &Path, symbol: &str) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read symbol specs: {}", path.display()))?;
+        let table: HashMap<String, SymbolSpec> = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse symbol specs: {}", path.display()))?;
+        Ok(table
+            .into_iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(symbol))
+            .map(|(_, spec)| spec))
+    }
+
+    /// Size of one pip: ten points on fractional-pip (3/5 digit) quotes, one point otherwise.
+    pub fn pip(&self) -> f64 {
+        if self.digits == 3 || self.digits == 5 {
+            self.point * 10.0
+        } else {
+            self.point
+        }
+    }
+
+    /// Account units per quote-currency unit implied by a fixed `tick_value`.
+    pub fn tick_value_rate(&self) -> Option<f64> {
+        let tick_value = self.tick_value?;
+        let per_tick = self.tick_size * self.contract_size;
+        (per_tick > 0.0).then(|| tick_value / per_tick)
+    }
+
+    /// Rounds `lots` down to the volume step; `None` if it falls outside the lot limits.
+    pub fn normalize_lots(&self, lots: f64) -> Option<f64> {
+        let step = if self.lot_step > 0.0 {
+            self.lot_step
+        } else {
+            0.01
+        };
+        let steps = (lots / step + 1e-9).floor();
+        let v = (steps * step * 1e8).round() / 1e8;
+        (v >= self.min_lot && v <= self.max_lot && v > 0.0).then_some(v)
+    }
+}
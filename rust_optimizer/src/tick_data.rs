@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::sim_broker::{BAR_SECONDS, Bar};
+
+/// One quote from a tick file, timed to the millisecond.
+#[derive(Clone, Debug)]
+pub struct Tick {
+    pub ts_ms: i64,
+    pub bid: f64,
+    pub ask: f64,
+}
+
+impl Tick {
+    /// The tick time in whole seconds, like `Bar::ts`.
+    pub fn ts(&self) -> i64 {
+        self.ts_ms.div_euclid(1000)
+    }
+}
+
+/// Reads `timestamp,bid,ask[,bid_volume,ask_volume]` rows with millisecond timestamps.
+/// Volume columns are accepted but not used; rows that do not parse or have `ask < bid`
+/// are skipped.
+pub fn load_ticks_from_csv(path: &Path) -> Result<Vec<Tick>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_path(path)
+        .with_context(|| format!("failed to open tick csv: {}", path.display()))?;
+
+    let mut ticks = Vec::new();
+    for rec in rdr.records() {
+        let r = match rec {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let ts_ms = r.get(0).and_then(|x| x.parse::<i64>().ok());
+        let bid = r.get(1).and_then(|x| x.parse::<f64>().ok());
+        let ask = r.get(2).and_then(|x| x.parse::<f64>().ok());
+        if let (Some(ts_ms), Some(bid), Some(ask)) = (ts_ms, bid, ask)
+            && bid > 0.0
+            && ask >= bid
+        {
+            ticks.push(Tick { ts_ms, bid, ask });
+        }
+    }
+    ticks.sort_by_key(|t| t.ts_ms);
+    Ok(ticks)
+}
+
+/// Builds bid bars from `ticks`; bars without any tick are left out, as in MT4 history.
+pub fn ticks_to_bars(ticks: &[Tick]) -> Vec<Bar> {
+    let mut bars: Vec<Bar> = Vec::new();
+    for t in ticks {
+        let ts = t.ts().div_euclid(BAR_SECONDS) * BAR_SECONDS;
+        match bars.last_mut() {
+            Some(b) if b.ts == ts => {
+                b.high = b.high.max(t.bid);
+                b.low = b.low.min(t.bid);
+                b.close = t.bid;
+            }
+            _ => bars.push(Bar {
+                ts,
+                open: t.bid,
+                high: t.bid,
+                low: t.bid,
+                close: t.bid,
+                spread: f64::NAN,
+            }),
+        }
+    }
+    bars
+}
+
+/// The ticks falling inside `[from_ts, to_ts)`, given in seconds.
+pub fn ticks_between(ticks: &[Tick], from_ts: i64, to_ts: i64) -> &[Tick] {
+    let lo = ticks.partition_point(|t| t.ts_ms < from_ts * 1000);
+    let hi = ticks.partition_point(|t| t.ts_ms < to_ts * 1000);
+    &ticks[lo..hi.max(lo)]
+}
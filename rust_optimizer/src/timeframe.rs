@@ -0,0 +1,80 @@
+use chrono::{DateTime, Datelike, NaiveDate};
+
+use crate::sim_broker::Bar;
+
+/// MT4 chart periods. The MQL `ENUM_TIMEFRAMES` value of each is its length in minutes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timeframe {
+    M1,
+    M5,
+    M15,
+    M30,
+    H1,
+    H4,
+    D1,
+    W1,
+    MN1,
+}
+
+impl Timeframe {
+    /// Maps an `ENUM_TIMEFRAMES` value. `PERIOD_CURRENT` (0) is the M5 chart the simulation
+    /// runs on; values MT4 does not know give `None`.
+    pub fn from_mql(minutes: i32) -> Option<Self> {
+        Some(match minutes {
+            0 | 5 => Self::M5,
+            1 => Self::M1,
+            15 => Self::M15,
+            30 => Self::M30,
+            60 => Self::H1,
+            240 => Self::H4,
+            1440 => Self::D1,
+            10080 => Self::W1,
+            43200 => Self::MN1,
+            _ => return None,
+        })
+    }
+
+    pub fn minutes(self) -> i64 {
+        match self {
+            Self::M1 => 1,
+            Self::M5 => 5,
+            Self::M15 => 15,
+            Self::M30 => 30,
+            Self::H1 => 60,
+            Self::H4 => 240,
+            Self::D1 => 1440,
+            Self::W1 => 10080,
+            Self::MN1 => 43200,
+        }
+    }
+
+    /// Open time of the bar of this period containing `ts`, as `iTime` reports it: periods
+    /// up to D1 are aligned to the epoch, weeks start on Sunday and months on the 1st.
+    pub fn bar_start(self, ts: i64) -> i64 {
+        match self {
+            Self::W1 => {
+                // 1970-01-04 was a Sunday.
+                let sunday = 3 * 86_400;
+                (ts - sunday).div_euclid(7 * 86_400) * 7 * 86_400 + sunday
+            }
+            Self::MN1 => DateTime::from_timestamp(ts, 0)
+                .and_then(|dt| NaiveDate::from_ymd_opt(dt.year(), dt.month(), 1))
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+                .map(|dt| dt.and_utc().timestamp())
+                .unwrap_or(ts),
+            tf => {
+                let len = tf.minutes() * 60;
+                ts.div_euclid(len) * len
+            }
+        }
+    }
+}
+
+/// Resamples `bars` (M5, sorted by time) onto `tf`: for each bar, the open time of the `tf`
+/// bar it falls into. Periods below M5 cannot be built from M5 bars and keep the M5 times.
+pub fn frame_bar_times(bars: &[Bar], tf: Timeframe) -> Vec<i64> {
+    if tf.minutes() <= Timeframe::M5.minutes() {
+        return bars.iter().map(|b| b.ts).collect();
+    }
+    bars.iter().map(|b| tf.bar_start(b.ts)).collect()
+}
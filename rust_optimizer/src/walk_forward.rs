@@ -0,0 +1,251 @@
+use std::ops::Range;
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::search_space::SearchSpace;
+use crate::sim_broker::{Bar, SimConfig};
+use crate::{
+    INITIAL_BALANCE, YearResult, agg_num, evaluate_params, is_feasible, optimize_params, round_to,
+    ts_to_utc,
+};
+
+/// Fold layout: each in-sample window of yearly windows is optimized and the winner is run
+/// on the windows right after it.
+#[derive(Clone, Copy, Debug)]
+pub struct WalkForward {
+    pub in_sample_years: usize,
+    pub out_of_sample_years: usize,
+    /// Every in-sample window starts at the first year instead of rolling forward.
+    pub anchored: bool,
+}
+
+impl WalkForward {
+    /// In-sample and out-of-sample year ranges of each fold. The last fold's out-of-sample
+    /// window may be shorter.
+    fn folds(self, years: usize) -> Vec<(Range<usize>, Range<usize>)> {
+        let mut out = Vec::new();
+        let mut oos_start = self.in_sample_years;
+        while oos_start < years {
+            let is_start = if self.anchored {
+                0
+            } else {
+                oos_start - self.in_sample_years
+            };
+            let oos_end = (oos_start + self.out_of_sample_years).min(years);
+            out.push((is_start..oos_start, oos_start..oos_end));
+            oos_start = oos_end;
+        }
+        out
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Fold {
+    fold: usize,
+    /// 1-based yearly windows, inclusive.
+    in_sample_years: [usize; 2],
+    out_of_sample_years: [usize; 2],
+    in_sample_start_utc: String,
+    out_of_sample_start_utc: String,
+    out_of_sample_end_utc: String,
+    feasible_in_sample: bool,
+    params: Map<String, Value>,
+    in_sample: Value,
+    out_of_sample: Value,
+    /// Out-of-sample net profit per year over in-sample net profit per year.
+    efficiency: Option<f64>,
+    yearly_results: Vec<YearResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalkForwardReport {
+    mode: &'static str,
+    in_sample_years: usize,
+    out_of_sample_years: usize,
+    folds: Vec<Fold>,
+    oos_net_profit: f64,
+    oos_blowup_years: usize,
+    /// Walk-forward efficiency: out-of-sample net profit per year over the mean in-sample
+    /// net profit per year of the fold winners. `None` when the in-sample mean is not
+    /// positive.
+    efficiency: Option<f64>,
+    /// Daily equity of the out-of-sample years run back to back, each year's profit carried
+    /// into the next, starting from the initial balance.
+    oos_equity: Vec<(String, f64)>,
+}
+
+impl WalkForwardReport {
+    pub fn folds(&self) -> usize {
+        self.folds.len()
+    }
+
+    pub fn oos_net_profit(&self) -> f64 {
+        self.oos_net_profit
+    }
+
+    pub fn efficiency(&self) -> Option<f64> {
+        self.efficiency
+    }
+}
+
+/// Runs the elite search on every in-sample window and the fold winner (best feasible, else
+/// best overall) on the out-of-sample window after it.
+#[allow(clippy::too_many_arguments)]
+pub fn walk_forward(
+    plan: WalkForward,
+    space: &SearchSpace,
+    symbol: &str,
+    yearly_bars: &[&[Bar]],
+    sim: &SimConfig,
+    drawdown_limit: f64,
+    trials: usize,
+    seed: u64,
+) -> Result<WalkForwardReport> {
+    if plan.in_sample_years == 0 || plan.out_of_sample_years == 0 {
+        bail!("walk-forward windows must be at least one year");
+    }
+    let layout = plan.folds(yearly_bars.len());
+    if layout.is_empty() {
+        bail!(
+            "walk-forward needs more than {} years of data, got {}",
+            plan.in_sample_years,
+            yearly_bars.len()
+        );
+    }
+
+    let clock = sim.server_time;
+    let first_utc = |bars: &[Bar]| ts_to_utc(clock.to_utc(bars.first().map_or(0, |b| b.ts)));
+    let last_utc = |bars: &[Bar]| ts_to_utc(clock.to_utc(bars.last().map_or(0, |b| b.ts)));
+
+    let mut folds = Vec::with_capacity(layout.len());
+    let mut oos_equity = Vec::new();
+    let mut balance = INITIAL_BALANCE;
+    let (mut oos_net, mut oos_years, mut is_rate_sum) = (0.0, 0, 0.0);
+    for (i, (is_range, oos_range)) in layout.into_iter().enumerate() {
+        println!(
+            "\n[walk-forward fold {}] in-sample years {}-{}, out-of-sample years {}-{}",
+            i + 1,
+            is_range.start + 1,
+            is_range.end,
+            oos_range.start + 1,
+            oos_range.end
+        );
+        let is_bars = &yearly_bars[is_range.clone()];
+        let oos_bars = &yearly_bars[oos_range.clone()];
+        let (best_any, best_feasible, _, _) = optimize_params(
+            space,
+            symbol,
+            is_bars,
+            sim,
+            drawdown_limit,
+            trials,
+            seed.wrapping_add(i as u64),
+        )?;
+        let winner = best_feasible.unwrap_or(best_any);
+
+        let (_, mut years, agg) =
+            evaluate_params(&winner.params, oos_bars, symbol, sim, drawdown_limit);
+        for y in &mut years {
+            y.year_idx += oos_range.start;
+        }
+        let is_rate = agg_num(&winner.agg, "sum_net_profit") / is_bars.len() as f64;
+        let oos_fold_net = agg_num(&agg, "sum_net_profit");
+        let oos_rate = oos_fold_net / oos_bars.len() as f64;
+        println!(
+            "[walk-forward fold {}] in-sample net/year={:.2} out-of-sample net/year={:.2}",
+            i + 1,
+            is_rate,
+            oos_rate
+        );
+
+        for y in &years {
+            for &(ts, eq) in &y.daily_equity {
+                oos_equity.push((
+                    ts_to_utc(clock.to_utc(ts)),
+                    round_to(balance + eq - INITIAL_BALANCE, 2),
+                ));
+            }
+            balance += y.net_profit;
+        }
+        oos_net += oos_fold_net;
+        oos_years += oos_bars.len();
+        is_rate_sum += is_rate;
+
+        folds.push(Fold {
+            fold: i + 1,
+            in_sample_years: [is_range.start + 1, is_range.end],
+            out_of_sample_years: [oos_range.start + 1, oos_range.end],
+            in_sample_start_utc: first_utc(is_bars[0]),
+            out_of_sample_start_utc: first_utc(oos_bars[0]),
+            out_of_sample_end_utc: last_utc(oos_bars[oos_bars.len() - 1]),
+            feasible_in_sample: is_feasible(&winner.agg),
+            params: winner.params,
+            in_sample: winner.agg,
+            out_of_sample: agg,
+            efficiency: (is_rate > 0.0).then(|| oos_rate / is_rate),
+            yearly_results: years,
+        });
+    }
+
+    let is_rate_mean = is_rate_sum / folds.len() as f64;
+    let oos_blowup_years = folds
+        .iter()
+        .flat_map(|f| &f.yearly_results)
+        .filter(|y| y.blew_up)
+        .count();
+    Ok(WalkForwardReport {
+        mode: if plan.anchored { "anchored" } else { "rolling" },
+        in_sample_years: plan.in_sample_years,
+        out_of_sample_years: plan.out_of_sample_years,
+        folds,
+        oos_net_profit: oos_net,
+        oos_blowup_years,
+        efficiency: (is_rate_mean > 0.0).then(|| oos_net / oos_years as f64 / is_rate_mean),
+        oos_equity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(in_sample_years: usize, out_of_sample_years: usize, anchored: bool) -> WalkForward {
+        WalkForward {
+            in_sample_years,
+            out_of_sample_years,
+            anchored,
+        }
+    }
+
+    #[test]
+    fn rolling_folds_move_the_in_sample_window() {
+        assert_eq!(
+            plan(3, 1, false).folds(6),
+            [(0..3, 3..4), (1..4, 4..5), (2..5, 5..6)]
+        );
+        assert_eq!(plan(2, 2, false).folds(6), [(0..2, 2..4), (2..4, 4..6)]);
+    }
+
+    #[test]
+    fn anchored_folds_start_at_the_first_year() {
+        assert_eq!(
+            plan(3, 1, true).folds(6),
+            [(0..3, 3..4), (0..4, 4..5), (0..5, 5..6)]
+        );
+        assert_eq!(plan(2, 2, true).folds(6), [(0..2, 2..4), (0..4, 4..6)]);
+    }
+
+    #[test]
+    fn last_out_of_sample_window_may_be_short() {
+        assert_eq!(plan(2, 3, false).folds(7), [(0..2, 2..5), (3..5, 5..7)]);
+        assert_eq!(plan(2, 3, true).folds(7), [(0..2, 2..5), (0..5, 5..7)]);
+    }
+
+    #[test]
+    fn no_fold_without_out_of_sample_years() {
+        assert!(plan(3, 1, false).folds(3).is_empty());
+        assert!(plan(3, 1, true).folds(2).is_empty());
+    }
+}